@@ -1,16 +1,12 @@
-use std::env::args;
-
 use chatgpt::prelude::*;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Getting the API key here
-    let key = args().nth(1).unwrap();
-
     // Creating a new ChatGPT client.
+    // The API key is read from the `OPENAI_API_KEY` environment variable.
     // Note that it requires an API key, and uses
     // tokens from your OpenAI API account balance.
-    let client = ChatGPT::new(key)?;
+    let client = ChatGPT::from_env()?;
 
     // Sending a message and getting the completion
     let response = client
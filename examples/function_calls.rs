@@ -40,13 +40,15 @@ async fn main() -> Result<()> {
     let key = args().nth(1).unwrap();
 
     // Creating a new ChatGPT client and conversation
-    let client = ChatGPT::new_with_config(
-        key,
-        ModelConfigurationBuilder::default()
-            .function_validation(FunctionValidationStrategy::Strict)
-            .build()
-            .unwrap(),
-    )?;
+    let client = ChatGPT::builder()
+        .api_key(key)
+        .config(
+            ModelConfigurationBuilder::default()
+                .function_validation(FunctionValidationStrategy::Strict)
+                .build()
+                .unwrap(),
+        )
+        .build()?;
     let mut conv = client.new_conversation();
 
     // Adding the functions
@@ -1,4 +1,4 @@
-use std::{env::args, str::FromStr};
+use std::env::args;
 
 use chatgpt::prelude::*;
 
@@ -9,15 +9,17 @@ async fn main() -> Result<()> {
 
     // Creating a new ChatGPT client with extra settings.
     // Note that it might not require an API key depending on proxy
-    let client = ChatGPT::new_with_config(
-        key,
-        ModelConfigurationBuilder::default()
-            .api_url(Url::from_str("https://api.pawan.krd/v1/chat/completions").unwrap())
-            .temperature(1.0)
-            .engine(ChatGPTEngine::Gpt4_32k)
-            .build()
-            .unwrap(),
-    )?;
+    let client = ChatGPT::builder()
+        .api_key(key)
+        .base_url("https://api.pawan.krd/v1/")
+        .config(
+            ModelConfigurationBuilder::default()
+                .temperature(1.0)
+                .engine(ChatGPTEngine::Gpt4_32k)
+                .build()
+                .unwrap(),
+        )
+        .build()?;
 
     // Sending a message and getting the completion
     let response = client
@@ -81,9 +81,9 @@ fn build_function_descriptor(input: &ItemFn, docs: &FunctionDocs) -> proc_macro2
             descriptor: FunctionDescriptor {
                 name: stringify!(#fn_name),
                 description: #description,
-                parameters: core::marker::PhantomData::<#aname>::default()
+                parameters: core::marker::PhantomData::<#aname>
             },
-            callable: core::marker::PhantomData::<#cname>::default()
+            callable: core::marker::PhantomData::<#cname>
         }
     )
 }
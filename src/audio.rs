@@ -0,0 +1,474 @@
+use std::time::Duration;
+
+use derive_builder::Builder;
+use reqwest::multipart::Form;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "streams")]
+use {futures_util::Stream, futures_util::StreamExt};
+
+use crate::client::{decode_response, error_for_status, with_retries, ChatGPT};
+use crate::config::RequestOptions;
+use crate::types::UploadFile;
+
+/// Minimum timeout of audio requests, as uploading audio and generating speech take much longer than completions
+const AUDIO_TIMEOUT: Duration = Duration::from_secs(300);
+/// How often failed audio requests are retried, if the error is retryable
+const AUDIO_RETRIES: u32 = 2;
+/// Delay before the first retry of a failed audio request
+const AUDIO_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Format of the transcription returned by the API
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionFormat {
+    /// Just the text, as JSON
+    #[default]
+    Json,
+    /// Just the text, as plain text
+    Text,
+    /// SubRip subtitles
+    Srt,
+    /// WebVTT subtitles
+    Vtt,
+    /// The text along with the language, duration and timestamps
+    VerboseJson,
+}
+
+impl TranscriptionFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TranscriptionFormat::Json => "json",
+            TranscriptionFormat::Text => "text",
+            TranscriptionFormat::Srt => "srt",
+            TranscriptionFormat::Vtt => "vtt",
+            TranscriptionFormat::VerboseJson => "verbose_json",
+        }
+    }
+}
+
+/// Granularity of the timestamps in [`TranscriptionFormat::VerboseJson`] transcriptions
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampGranularity {
+    /// Timestamps for each word
+    Word,
+    /// Timestamps for each segment
+    Segment,
+}
+
+/// Options for transcriptions and translations of audio
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(default, setter(into))]
+pub struct TranscriptionOptions {
+    /// The speech recognition model, e.g. `whisper-1`
+    pub model: String,
+    /// Language of the audio as an ISO-639-1 code, e.g. `en`. Ignored for translations.
+    #[builder(setter(strip_option))]
+    pub language: Option<String>,
+    /// Text guiding the style of the transcription, or continuing a previous segment
+    #[builder(setter(strip_option))]
+    pub prompt: Option<String>,
+    /// Format of the returned transcription
+    pub response_format: TranscriptionFormat,
+    /// Sampling temperature between 0 and 1
+    #[builder(setter(strip_option))]
+    pub temperature: Option<f32>,
+    /// Granularities of the timestamps. Requires [`TranscriptionFormat::VerboseJson`], ignored for translations.
+    pub timestamp_granularities: Vec<TimestampGranularity>,
+}
+
+impl Default for TranscriptionOptions {
+    fn default() -> Self {
+        Self {
+            model: "whisper-1".to_string(),
+            language: None,
+            prompt: None,
+            response_format: TranscriptionFormat::Json,
+            temperature: None,
+            timestamp_granularities: Vec::new(),
+        }
+    }
+}
+
+/// A transcription or translation of audio.
+///
+/// For the text based formats, only the `text` is set, containing the whole response, e.g. the subtitles.
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Deserialize)]
+pub struct Transcription {
+    /// The transcribed text
+    pub text: String,
+    /// Detected language of the audio
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Duration of the audio in seconds
+    #[serde(default)]
+    pub duration: Option<f64>,
+    /// Timestamps of each word, if requested
+    #[serde(default)]
+    pub words: Vec<TranscriptionWord>,
+    /// Timestamps of each segment, if requested
+    #[serde(default)]
+    pub segments: Vec<TranscriptionSegment>,
+}
+
+/// A single transcribed word with its timestamps
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct TranscriptionWord {
+    /// The word
+    pub word: String,
+    /// Start of the word in seconds
+    pub start: f64,
+    /// End of the word in seconds
+    pub end: f64,
+}
+
+/// A single transcribed segment with its timestamps
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+pub struct TranscriptionSegment {
+    /// Index of the segment
+    pub id: u32,
+    /// Text of the segment
+    pub text: String,
+    /// Start of the segment in seconds
+    pub start: f64,
+    /// End of the segment in seconds
+    pub end: f64,
+}
+
+/// Voice used for generated speech
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Voice {
+    /// The `alloy` voice
+    #[default]
+    Alloy,
+    /// The `echo` voice
+    Echo,
+    /// The `fable` voice
+    Fable,
+    /// The `onyx` voice
+    Onyx,
+    /// The `nova` voice
+    Nova,
+    /// The `shimmer` voice
+    Shimmer,
+}
+
+/// Audio format of generated speech
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    /// MP3 audio
+    #[default]
+    Mp3,
+    /// Opus audio, for low latency streaming
+    Opus,
+    /// AAC audio
+    Aac,
+    /// Lossless FLAC audio
+    Flac,
+    /// Uncompressed WAV audio
+    Wav,
+    /// Raw 24kHz 16-bit signed little-endian samples
+    Pcm,
+}
+
+/// Options for generated speech
+#[derive(Debug, Clone, PartialEq, Serialize, Builder)]
+#[builder(default, setter(into))]
+pub struct SpeechOptions {
+    /// The text-to-speech model, e.g. `tts-1` or `tts-1-hd`
+    pub model: String,
+    /// The voice to use
+    pub voice: Voice,
+    /// Audio format of the generated speech
+    pub response_format: SpeechFormat,
+    /// Speed of the speech, between 0.25 and 4.0
+    #[builder(setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+}
+
+impl Default for SpeechOptions {
+    fn default() -> Self {
+        Self {
+            model: "tts-1".to_string(),
+            voice: Voice::Alloy,
+            response_format: SpeechFormat::Mp3,
+            speed: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SpeechRequest<'a> {
+    input: &'a str,
+    #[serde(flatten)]
+    options: &'a SpeechOptions,
+}
+
+impl ChatGPT {
+    /// Transcribes the audio into the input language using the `/v1/audio/transcriptions` endpoint
+    pub async fn transcribe(
+        &self,
+        file: UploadFile,
+        options: &TranscriptionOptions,
+    ) -> crate::Result<Transcription> {
+        self.transcribe_with_options(file, options, &RequestOptions::default())
+            .await
+    }
+
+    /// Transcribes the audio into the input language, applying the provided per-call options.
+    ///
+    /// Audio requests time out after at least 5 minutes unless a deadline is set, and retryable errors are retried twice.
+    pub async fn transcribe_with_options(
+        &self,
+        file: UploadFile,
+        options: &TranscriptionOptions,
+        request_options: &RequestOptions,
+    ) -> crate::Result<Transcription> {
+        self.send_audio("audio/transcriptions", file, options, request_options, true)
+            .await
+    }
+
+    /// Translates the audio into English using the `/v1/audio/translations` endpoint
+    pub async fn translate_audio(
+        &self,
+        file: UploadFile,
+        options: &TranscriptionOptions,
+    ) -> crate::Result<Transcription> {
+        self.translate_audio_with_options(file, options, &RequestOptions::default())
+            .await
+    }
+
+    /// Translates the audio into English, applying the provided per-call options.
+    ///
+    /// See [`Self::transcribe_with_options()`] for details.
+    pub async fn translate_audio_with_options(
+        &self,
+        file: UploadFile,
+        options: &TranscriptionOptions,
+        request_options: &RequestOptions,
+    ) -> crate::Result<Transcription> {
+        self.send_audio("audio/translations", file, options, request_options, false)
+            .await
+    }
+
+    /// Generates spoken audio from the input text using the `/v1/audio/speech` endpoint
+    pub async fn speech<S: AsRef<str>>(
+        &self,
+        input: S,
+        options: &SpeechOptions,
+    ) -> crate::Result<Vec<u8>> {
+        self.speech_with_options(input, options, &RequestOptions::default())
+            .await
+    }
+
+    /// Generates spoken audio from the input text, applying the provided per-call options.
+    ///
+    /// See [`Self::transcribe_with_options()`] for details.
+    pub async fn speech_with_options<S: AsRef<str>>(
+        &self,
+        input: S,
+        options: &SpeechOptions,
+        request_options: &RequestOptions,
+    ) -> crate::Result<Vec<u8>> {
+        let speech = async {
+            let response = self
+                .send_speech(input.as_ref(), options, request_options)
+                .await?;
+            Ok(response.bytes().await?.to_vec())
+        };
+        request_options
+            .run_until(request_options.deadline_instant(), speech)
+            .await
+    }
+
+    /// Generates spoken audio from the input text, returning the audio as a stream of byte chunks as it is generated
+    ///
+    /// Requires the `streams` crate feature
+    #[cfg(feature = "streams")]
+    pub async fn speech_streaming<S: AsRef<str>>(
+        &self,
+        input: S,
+        options: &SpeechOptions,
+    ) -> crate::Result<impl Stream<Item = crate::Result<Vec<u8>>>> {
+        let response = self
+            .send_speech(input.as_ref(), options, &RequestOptions::default())
+            .await?;
+        Ok(response.bytes_stream().map(|chunk| {
+            chunk
+                .map(|bytes| bytes.to_vec())
+                .map_err(crate::err::Error::from)
+        }))
+    }
+
+    /// The timeout of each attempt of an audio request
+    fn audio_timeout(&self, request_options: &RequestOptions) -> Duration {
+        request_options
+            .deadline
+            .unwrap_or(self.config.timeout.max(AUDIO_TIMEOUT))
+    }
+
+    async fn send_speech(
+        &self,
+        input: &str,
+        options: &SpeechOptions,
+        request_options: &RequestOptions,
+    ) -> crate::Result<reqwest::Response> {
+        let url = self.endpoint("audio/speech")?;
+        let timeout = self.audio_timeout(request_options);
+        with_retries(AUDIO_RETRIES, AUDIO_RETRY_DELAY, || async {
+            let response = self
+                .request(Method::POST, url.clone())
+                .timeout(timeout)
+                .json(&SpeechRequest { input, options })
+                .send()
+                .await?;
+            error_for_status(response).await
+        })
+        .await
+    }
+
+    async fn send_audio(
+        &self,
+        endpoint: &str,
+        file: UploadFile,
+        options: &TranscriptionOptions,
+        request_options: &RequestOptions,
+        transcription: bool,
+    ) -> crate::Result<Transcription> {
+        let url = self.endpoint(endpoint)?;
+        let timeout = self.audio_timeout(request_options);
+        let attempt = || async {
+            let response = self
+                .request(Method::POST, url.clone())
+                .timeout(timeout)
+                .multipart(audio_form(file.clone(), options, transcription)?)
+                .send()
+                .await?;
+            match options.response_format {
+                TranscriptionFormat::Json | TranscriptionFormat::VerboseJson => {
+                    decode_response(response).await
+                }
+                _ => Ok(Transcription {
+                    text: error_for_status(response).await?.text().await?,
+                    ..Default::default()
+                }),
+            }
+        };
+        request_options
+            .run_until(
+                request_options.deadline_instant(),
+                with_retries(AUDIO_RETRIES, AUDIO_RETRY_DELAY, attempt),
+            )
+            .await
+    }
+}
+
+/// Builds the multipart form of a transcription or translation request
+fn audio_form(
+    file: UploadFile,
+    options: &TranscriptionOptions,
+    transcription: bool,
+) -> crate::Result<Form> {
+    let mut form = Form::new()
+        .part("file", file.into_part()?)
+        .text("model", options.model.clone())
+        .text("response_format", options.response_format.as_str());
+    if let Some(prompt) = &options.prompt {
+        form = form.text("prompt", prompt.clone());
+    }
+    if let Some(temperature) = options.temperature {
+        form = form.text("temperature", temperature.to_string());
+    }
+    if transcription {
+        if let Some(language) = &options.language {
+            form = form.text("language", language.clone());
+        }
+        for granularity in &options.timestamp_granularities {
+            let granularity = match granularity {
+                TimestampGranularity::Word => "word",
+                TimestampGranularity::Segment => "segment",
+            };
+            form = form.text("timestamp_granularities[]", granularity);
+        }
+    }
+    Ok(form)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::{SpeechOptionsBuilder, Transcription, Voice};
+
+    #[test]
+    fn test_verbose_transcription() {
+        let transcription: Transcription = serde_json::from_str(
+            r#"{
+                "task": "transcribe",
+                "language": "english",
+                "duration": 1.5,
+                "text": "Hello world",
+                "words": [
+                    { "word": "Hello", "start": 0.0, "end": 0.6 },
+                    { "word": "world", "start": 0.7, "end": 1.4 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(transcription.words[1].word, "world");
+        assert_eq!(transcription.duration, Some(1.5));
+        assert!(transcription.segments.is_empty());
+    }
+
+    #[test]
+    fn test_audio_timeout() {
+        use std::time::Duration;
+
+        use crate::client::ChatGPT;
+        use crate::config::RequestOptionsBuilder;
+
+        let client = ChatGPT::new("key").unwrap();
+        // Audio requests are not limited by the short completion timeout
+        assert_eq!(
+            client.audio_timeout(&Default::default()),
+            Duration::from_secs(300)
+        );
+        let options = RequestOptionsBuilder::default()
+            .deadline(Duration::from_secs(30))
+            .build()
+            .unwrap();
+        assert_eq!(client.audio_timeout(&options), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_speech_request() {
+        let options = SpeechOptionsBuilder::default()
+            .voice(Voice::Nova)
+            .speed(1.25)
+            .build()
+            .unwrap();
+        let request = serde_json::to_value(super::SpeechRequest {
+            input: "Hello",
+            options: &options,
+        })
+        .unwrap();
+        assert_eq!(
+            request,
+            serde_json::json!({
+                "input": "Hello",
+                "model": "tts-1",
+                "voice": "nova",
+                "response_format": "mp3",
+                "speed": 1.25
+            })
+        );
+    }
+}
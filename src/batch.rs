@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use reqwest::multipart::Form;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::client::{decode_response, error_for_status, ChatGPT};
+use crate::err::{ApiError, Error};
+use crate::types::{
+    CompletionError, CompletionRequest, CompletionResponse, ResponseMetadata, UploadFile,
+};
+
+/// Results of a batch, mapped by the custom IDs of the requests
+pub type BatchResults = HashMap<String, crate::Result<CompletionResponse>>;
+
+/// Purpose of an uploaded file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilePurpose {
+    /// Input of a batch
+    Batch,
+    /// Output or errors of a batch, created by the API
+    BatchOutput,
+    /// Training data for fine-tuning
+    FineTune,
+    /// Files used by assistants
+    Assistants,
+}
+
+impl FilePurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FilePurpose::Batch => "batch",
+            FilePurpose::BatchOutput => "batch-output",
+            FilePurpose::FineTune => "fine-tune",
+            FilePurpose::Assistants => "assistants",
+        }
+    }
+}
+
+/// A file stored through the `/v1/files` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct FileObject {
+    /// ID of the file
+    pub id: String,
+    /// Size of the file in bytes
+    pub bytes: u64,
+    /// Unix seconds timestamp of when the file was created
+    pub created_at: u64,
+    /// Name of the file
+    pub filename: String,
+    /// Purpose of the file, e.g. `batch`
+    pub purpose: String,
+}
+
+/// Status of a batch
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    /// The input file is being validated
+    Validating,
+    /// The input file failed the validation
+    Failed,
+    /// The requests are being processed
+    InProgress,
+    /// The results are being prepared
+    Finalizing,
+    /// All requests were processed and the results are available
+    Completed,
+    /// The batch did not complete in the completion window, results of completed requests are available
+    Expired,
+    /// The batch is being cancelled
+    Cancelling,
+    /// The batch was cancelled, results of completed requests are available
+    Cancelled,
+}
+
+impl BatchStatus {
+    /// Checks whether the batch will not change its status anymore
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            BatchStatus::Failed
+                | BatchStatus::Completed
+                | BatchStatus::Expired
+                | BatchStatus::Cancelled
+        )
+    }
+}
+
+/// Progress of a batch
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub struct BatchRequestCounts {
+    /// Total amount of requests
+    pub total: u32,
+    /// Amount of successfully completed requests
+    pub completed: u32,
+    /// Amount of failed requests
+    pub failed: u32,
+}
+
+/// An error that occurred when validating the input of a batch
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct BatchValidationError {
+    /// Machine-readable error code
+    pub code: Option<String>,
+    /// Message, describing this error
+    pub message: String,
+    /// Line of the input file the error occurred in
+    pub line: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+struct BatchErrors {
+    data: Vec<BatchValidationError>,
+}
+
+/// A batch job, created through the `/v1/batches` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct Batch {
+    /// ID of the batch
+    pub id: String,
+    /// Current status of the batch
+    pub status: BatchStatus,
+    /// ID of the input file
+    pub input_file_id: String,
+    /// ID of the file with the successful results, once available
+    pub output_file_id: Option<String>,
+    /// ID of the file with the failed results, once available
+    pub error_file_id: Option<String>,
+    /// Progress of the batch
+    #[serde(default)]
+    pub request_counts: BatchRequestCounts,
+    /// Unix seconds timestamp of when the batch was created
+    pub created_at: u64,
+    /// Errors of the input validation, if the batch failed
+    #[serde(default, deserialize_with = "deserialize_errors")]
+    pub errors: Vec<BatchValidationError>,
+}
+
+fn deserialize_errors<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<BatchValidationError>, D::Error> {
+    let errors: Option<BatchErrors> = Deserialize::deserialize(deserializer)?;
+    Ok(errors.map(|errors| errors.data).unwrap_or_default())
+}
+
+#[derive(Serialize)]
+struct BatchInputLine<'a> {
+    custom_id: &'a str,
+    method: &'static str,
+    url: &'static str,
+    body: &'a CompletionRequest<'a>,
+}
+
+#[derive(Serialize)]
+struct CreateBatchRequest<'a> {
+    input_file_id: &'a str,
+    endpoint: &'static str,
+    completion_window: &'static str,
+}
+
+#[derive(Deserialize)]
+struct BatchOutputLine {
+    custom_id: String,
+    response: Option<BatchOutputResponse>,
+    error: Option<BatchLineError>,
+}
+
+#[derive(Deserialize)]
+struct BatchOutputResponse {
+    status_code: u16,
+    request_id: Option<String>,
+    body: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct BatchLineError {
+    code: Option<String>,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: CompletionError,
+}
+
+/// Serializes the completion requests into the JSON Lines input format of the batch API.
+///
+/// Each request is paired with a custom ID, which must be unique within the batch.
+pub fn to_jsonl<'a, I, S>(requests: I) -> crate::Result<Vec<u8>>
+where
+    I: IntoIterator<Item = (S, &'a CompletionRequest<'a>)>,
+    S: AsRef<str>,
+{
+    let mut output = Vec::new();
+    for (custom_id, request) in requests {
+        serde_json::to_writer(
+            &mut output,
+            &BatchInputLine {
+                custom_id: custom_id.as_ref(),
+                method: "POST",
+                url: "/v1/chat/completions",
+                body: request,
+            },
+        )?;
+        output.push(b'\n');
+    }
+    Ok(output)
+}
+
+/// Parses an output or error file of a batch, mapping the results to their custom IDs
+pub fn parse_results(data: &str) -> crate::Result<BatchResults> {
+    let mut results = HashMap::new();
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        let line: BatchOutputLine = serde_json::from_str(line)?;
+        results.insert(line.custom_id, parse_result(line.response, line.error));
+    }
+    Ok(results)
+}
+
+fn parse_result(
+    response: Option<BatchOutputResponse>,
+    error: Option<BatchLineError>,
+) -> crate::Result<CompletionResponse> {
+    match (response, error) {
+        (Some(response), None) if (200..300).contains(&response.status_code) => {
+            let completion: CompletionResponse = serde_json::from_value(response.body)?;
+            Ok(CompletionResponse {
+                metadata: Some(ResponseMetadata {
+                    status: response.status_code,
+                    request_id: response.request_id,
+                    ..Default::default()
+                }),
+                ..completion
+            })
+        }
+        (Some(response), _) => {
+            let error = serde_json::from_value::<ErrorBody>(response.body)
+                .map(|body| body.error)
+                .unwrap_or_else(|_| CompletionError {
+                    message: "The request failed without an error message".to_string(),
+                    error_type: String::new(),
+                    code: None,
+                });
+            Err(Error::from_api(
+                ApiError {
+                    message: error.message,
+                    error_type: error.error_type,
+                    code: error.code,
+                    status: response.status_code,
+                    request_id: response.request_id,
+                },
+                None,
+            ))
+        }
+        (None, Some(error)) => Err(Error::BackendError {
+            message: error.message,
+            error_type: "batch_error".to_string(),
+            code: error.code,
+            status: None,
+            request_id: None,
+        }),
+        (None, None) => Err(Error::ParsingError(
+            "Batch result contains neither a response nor an error".to_string(),
+        )),
+    }
+}
+
+impl ChatGPT {
+    /// Uploads a file using the `/v1/files` endpoint
+    pub async fn upload_file(
+        &self,
+        file: UploadFile,
+        purpose: FilePurpose,
+    ) -> crate::Result<FileObject> {
+        let form = Form::new()
+            .text("purpose", purpose.as_str())
+            .part("file", file.into_part()?);
+        let response = self
+            .request(Method::POST, self.endpoint("files")?)
+            .multipart(form)
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Retrieves the information about an uploaded file
+    pub async fn retrieve_file<S: AsRef<str>>(&self, id: S) -> crate::Result<FileObject> {
+        let response = self
+            .request(
+                Method::GET,
+                self.endpoint(&format!("files/{}", id.as_ref()))?,
+            )
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Downloads the contents of a file
+    pub async fn file_content<S: AsRef<str>>(&self, id: S) -> crate::Result<Vec<u8>> {
+        let response = self
+            .request(
+                Method::GET,
+                self.endpoint(&format!("files/{}/content", id.as_ref()))?,
+            )
+            .send()
+            .await?;
+        Ok(error_for_status(response).await?.bytes().await?.to_vec())
+    }
+
+    /// Deletes an uploaded file
+    pub async fn delete_file<S: AsRef<str>>(&self, id: S) -> crate::Result<()> {
+        let response = self
+            .request(
+                Method::DELETE,
+                self.endpoint(&format!("files/{}", id.as_ref()))?,
+            )
+            .send()
+            .await?;
+        error_for_status(response).await?;
+        Ok(())
+    }
+
+    /// Creates a batch of chat completions from an uploaded input file, with a 24 hour completion window
+    pub async fn create_batch<S: AsRef<str>>(&self, input_file_id: S) -> crate::Result<Batch> {
+        let response = self
+            .request(Method::POST, self.endpoint("batches")?)
+            .json(&CreateBatchRequest {
+                input_file_id: input_file_id.as_ref(),
+                endpoint: "/v1/chat/completions",
+                completion_window: "24h",
+            })
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Retrieves the current state of a batch
+    pub async fn retrieve_batch<S: AsRef<str>>(&self, id: S) -> crate::Result<Batch> {
+        let response = self
+            .request(
+                Method::GET,
+                self.endpoint(&format!("batches/{}", id.as_ref()))?,
+            )
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Cancels a batch. Results of already completed requests stay available.
+    pub async fn cancel_batch<S: AsRef<str>>(&self, id: S) -> crate::Result<Batch> {
+        let response = self
+            .request(
+                Method::POST,
+                self.endpoint(&format!("batches/{}/cancel", id.as_ref()))?,
+            )
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Polls the batch in the provided interval until it reaches a terminal status
+    pub async fn wait_for_batch<S: AsRef<str>>(
+        &self,
+        id: S,
+        poll_interval: Duration,
+    ) -> crate::Result<Batch> {
+        loop {
+            let batch = self.retrieve_batch(id.as_ref()).await?;
+            if batch.status.is_terminal() {
+                return Ok(batch);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Downloads the output and error files of a batch, mapping the results to their custom IDs
+    pub async fn batch_results(&self, batch: &Batch) -> crate::Result<BatchResults> {
+        let mut results = HashMap::new();
+        for file in [&batch.output_file_id, &batch.error_file_id]
+            .into_iter()
+            .flatten()
+        {
+            let content = String::from_utf8(self.file_content(file).await?)?;
+            results.extend(parse_results(&content)?);
+        }
+        Ok(results)
+    }
+
+    /// Runs the completion requests as a batch: uploads them, creates the batch, waits for it to finish and returns the results.
+    ///
+    /// Each request is paired with a custom ID, which must be unique within the batch.
+    /// Returns an error if the batch fails validation, and partial results if it expired or was cancelled.
+    pub async fn run_batch<'a, I, S>(
+        &self,
+        requests: I,
+        poll_interval: Duration,
+    ) -> crate::Result<BatchResults>
+    where
+        I: IntoIterator<Item = (S, &'a CompletionRequest<'a>)>,
+        S: AsRef<str>,
+    {
+        let input = to_jsonl(requests)?;
+        let file = self
+            .upload_file(
+                UploadFile::from_bytes("batch.jsonl", input),
+                FilePurpose::Batch,
+            )
+            .await?;
+        let batch = self.create_batch(&file.id).await?;
+        let batch = self.wait_for_batch(&batch.id, poll_interval).await?;
+        if batch.status == BatchStatus::Failed {
+            let message = batch
+                .errors
+                .iter()
+                .map(|error| error.message.as_str())
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(Error::BackendError {
+                message,
+                error_type: "batch_failed".to_string(),
+                code: None,
+                status: None,
+                request_id: None,
+            });
+        }
+        self.batch_results(&batch).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::{parse_results, to_jsonl, Batch, BatchStatus};
+    use crate::client::ChatGPT;
+    use crate::err::Error;
+    use crate::types::{ChatMessage, Role};
+
+    #[test]
+    fn test_batch_input() {
+        let client = ChatGPT::new("key").unwrap();
+        let history = vec![ChatMessage::new(Role::User, "Hello")];
+        let request = client.completion_request(&history, false);
+        let input =
+            String::from_utf8(to_jsonl([("first", &request), ("second", &request)]).unwrap())
+                .unwrap();
+        let lines: Vec<serde_json::Value> = input
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["custom_id"], "second");
+        assert_eq!(lines[0]["url"], "/v1/chat/completions");
+        assert_eq!(lines[0]["body"]["messages"][0]["content"], "Hello");
+    }
+
+    #[test]
+    fn test_batch_results() {
+        let output = r#"
+{"id": "batch_req_1", "custom_id": "first", "response": {"status_code": 200, "request_id": "req_1", "body": {"id": "chatcmpl-1", "created": 1700000000, "model": "gpt-3.5-turbo", "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}, "choices": [{"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "Hi"}}]}}, "error": null}
+{"id": "batch_req_2", "custom_id": "second", "response": {"status_code": 400, "request_id": "req_2", "body": {"error": {"message": "Too long", "type": "invalid_request_error", "code": "context_length_exceeded"}}}, "error": null}
+{"id": "batch_req_3", "custom_id": "third", "response": null, "error": {"code": "batch_expired", "message": "Expired"}}
+"#;
+        let results = parse_results(output).unwrap();
+        let first = results["first"].as_ref().unwrap();
+        assert_eq!(first.message().content, "Hi");
+        assert_eq!(
+            first.metadata.as_ref().unwrap().request_id.as_deref(),
+            Some("req_1")
+        );
+        assert!(matches!(
+            results["second"],
+            Err(Error::ContextLengthExceeded(_))
+        ));
+        assert_eq!(
+            results["third"].as_ref().unwrap_err().code(),
+            Some("batch_expired")
+        );
+    }
+
+    #[test]
+    fn test_batch_status() {
+        let batch: Batch = serde_json::from_str(
+            r#"{
+                "id": "batch_1", "object": "batch", "status": "failed", "input_file_id": "file-1",
+                "output_file_id": null, "error_file_id": null, "created_at": 1700000000,
+                "errors": { "object": "list", "data": [{ "code": "invalid_json", "message": "Invalid JSON", "line": 2 }] }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(batch.status, BatchStatus::Failed);
+        assert!(batch.status.is_terminal());
+        assert_eq!(batch.errors[0].line, Some(2));
+    }
+}
@@ -0,0 +1,418 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::RequestOptions;
+use crate::converse::Conversation;
+use crate::err::Error;
+use crate::types::{ChatMessage, CompletionResponse};
+
+/// A single message in a [`HistoryTree`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeNode {
+    /// The message itself
+    pub message: ChatMessage,
+    /// ID of the message preceding this one, or `None` for the first message
+    pub parent: Option<usize>,
+}
+
+/// All messages that were ever part of the history of a [`Conversation`], including the alternative branches.
+///
+/// The history of the conversation is the active path through the tree. Messages are identified by their index in the tree.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryTree {
+    nodes: Vec<TreeNode>,
+    active: Vec<usize>,
+}
+
+impl HistoryTree {
+    /// Returns the amount of messages in the tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks whether the tree contains no messages
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the message with the provided ID
+    pub fn get(&self, id: usize) -> Option<&TreeNode> {
+        self.nodes.get(id)
+    }
+
+    /// Returns the IDs of the messages of the active path, which match the history of the conversation
+    pub fn active(&self) -> &[usize] {
+        &self.active
+    }
+
+    /// Returns the IDs of the messages following the provided one, or of the first messages if `None` is passed
+    pub fn children(&self, parent: Option<usize>) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|id| self.nodes[*id].parent == parent)
+            .collect()
+    }
+
+    /// Returns the IDs of the messages at the provided position of the active path, including the active one itself
+    pub fn alternatives(&self, index: usize) -> Vec<usize> {
+        if index >= self.active.len() {
+            return Vec::new();
+        }
+        let parent = index.checked_sub(1).map(|previous| self.active[previous]);
+        self.children(parent)
+    }
+
+    /// Checks whether the active path matches the history
+    pub(crate) fn is_recorded(&self, history: &[ChatMessage]) -> bool {
+        self.active.len() == history.len()
+            && self
+                .active
+                .iter()
+                .zip(history)
+                .all(|(id, message)| self.nodes[*id].message == *message)
+    }
+
+    /// Records the history as the active path, adding the messages that are not in the tree yet
+    pub(crate) fn record(&mut self, history: &[ChatMessage]) {
+        let shared = self
+            .active
+            .iter()
+            .zip(history)
+            .take_while(|(id, message)| self.nodes[**id].message == **message)
+            .count();
+        self.active.truncate(shared);
+        for message in &history[shared..] {
+            let parent = self.active.last().copied();
+            let existing = self
+                .children(parent)
+                .into_iter()
+                .find(|id| self.nodes[*id].message == *message);
+            let id = existing.unwrap_or_else(|| {
+                self.nodes.push(TreeNode {
+                    message: message.clone(),
+                    parent,
+                });
+                self.nodes.len() - 1
+            });
+            self.active.push(id);
+        }
+    }
+
+    /// Makes the path through the provided message the active one, continuing with the latest messages after it.
+    /// Returns the new history.
+    pub(crate) fn switch(&mut self, id: usize) -> crate::Result<Vec<ChatMessage>> {
+        if id >= self.nodes.len() {
+            return Err(Error::UnknownBranch(id));
+        }
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[*path.last().unwrap()].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        while let Some(child) = self.children(path.last().copied()).pop() {
+            path.push(child);
+        }
+        self.active = path;
+        Ok(self
+            .active
+            .iter()
+            .map(|id| self.nodes[*id].message.clone())
+            .collect())
+    }
+}
+
+impl Conversation {
+    /// Returns the tree of all messages of this conversation, including the alternative branches.
+    ///
+    /// The tree is copied if the history was modified directly since the tree was last updated.
+    pub fn tree(&self) -> Cow<'_, HistoryTree> {
+        if self.tree.is_recorded(&self.history) {
+            return Cow::Borrowed(&self.tree);
+        }
+        let mut tree = self.tree.clone();
+        tree.record(&self.history);
+        Cow::Owned(tree)
+    }
+
+    /// Returns the alternatives to the message at the provided index of the history, including the message itself.
+    /// Each alternative is returned with its ID, that can be passed to [`Self::switch_branch()`].
+    pub fn alternatives(&self, index: usize) -> crate::Result<Vec<(usize, ChatMessage)>> {
+        self.check_index(index)?;
+        if self.tree.is_empty() {
+            // Without branches, the IDs match the indices of the history
+            return Ok(vec![(index, self.history[index].clone())]);
+        }
+        let tree = self.tree();
+        Ok(tree
+            .alternatives(index)
+            .into_iter()
+            .map(|id| (id, tree.nodes[id].message.clone()))
+            .collect())
+    }
+
+    /// Forks the conversation after the message at the provided index, removing the following messages from the history.
+    ///
+    /// The removed messages are kept as a branch, so the next sent message starts a new branch next to them.
+    pub fn fork_at(&mut self, index: usize) -> crate::Result<()> {
+        self.check_index(index)?;
+        self.tree.record(&self.history);
+        self.truncate_history(index + 1);
+        Ok(())
+    }
+
+    /// Switches the history to the branch containing the message with the provided ID.
+    ///
+    /// If the message has following messages, the history continues with the most recently added ones.
+    pub fn switch_branch(&mut self, id: usize) -> crate::Result<()> {
+        self.tree.record(&self.history);
+        let history = self.tree.switch(id)?;
+        let shared = self
+            .history
+            .iter()
+            .zip(&history)
+            .take_while(|(current, new)| current == new)
+            .count();
+        self.truncate_history(shared);
+        self.history.extend_from_slice(&history[shared..]);
+        Ok(())
+    }
+
+    /// Requests a new reply to the last user message, keeping the previous reply as an alternative branch.
+    ///
+    /// All messages after the last user message, e.g. function calls and their results, are replaced.
+    pub async fn regenerate_last(&mut self) -> crate::Result<CompletionResponse> {
+        self.regenerate_last_with_options(&RequestOptions::default())
+            .await
+    }
+
+    /// Requests a new reply to the last user message, applying the provided per-call options.
+    ///
+    /// See [`Self::regenerate_last()`] for details. If the request fails, the previous reply is restored.
+    pub async fn regenerate_last_with_options(
+        &mut self,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse> {
+        let Some(last_user) = self.last_user_message() else {
+            return Err(Error::NoUserMessage);
+        };
+        self.tree.record(&self.history);
+        let previous = self.truncate_history(last_user + 1);
+        match self
+            .complete_and_process(0, self.sends_functions(), options, None)
+            .await
+        {
+            Ok(response) => {
+                self.tree.record(&self.history);
+                #[cfg(feature = "json")]
+                self.sync_store().await?;
+                Ok(response)
+            }
+            Err(err) => {
+                self.truncate_history(last_user + 1);
+                self.history.extend(previous);
+                Err(err)
+            }
+        }
+    }
+
+    /// Replaces the message at the provided index with the new content, and sends it again,
+    /// keeping the original message and the turns following it as an alternative branch.
+    ///
+    /// The message is sent with its original role. If it was a user message, it is moderated and its context is retrieved again.
+    pub async fn edit_message<S: Into<String>>(
+        &mut self,
+        index: usize,
+        content: S,
+    ) -> crate::Result<CompletionResponse> {
+        self.edit_message_with_options(index, content, &RequestOptions::default())
+            .await
+    }
+
+    /// Replaces the message at the provided index with the new content, and sends it again, applying the provided per-call options.
+    ///
+    /// See [`Self::edit_message()`] for details. If the request fails, the original history is restored.
+    pub async fn edit_message_with_options<S: Into<String>>(
+        &mut self,
+        index: usize,
+        content: S,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse> {
+        self.check_index(index)?;
+        let role = self.history[index].role;
+        let start = self.turn_start(index);
+        self.tree.record(&self.history);
+        let previous = self.truncate_history(start);
+        match self
+            .send_and_process(
+                ChatMessage::new(role, content),
+                self.sends_functions(),
+                options,
+                None,
+            )
+            .await
+        {
+            Ok(response) => {
+                self.tree.record(&self.history);
+                #[cfg(feature = "json")]
+                self.sync_store().await?;
+                Ok(response)
+            }
+            Err(err) => {
+                self.truncate_history(start);
+                self.history.extend(previous);
+                Err(err)
+            }
+        }
+    }
+
+    /// Fails if the index does not point to a message in the history
+    pub(crate) fn check_index(&self, index: usize) -> crate::Result<()> {
+        if index < self.history.len() {
+            Ok(())
+        } else {
+            Err(Error::HistoryIndexOutOfBounds {
+                index,
+                len: self.history.len(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::branch::HistoryTree;
+    use crate::client::ChatGPT;
+    use crate::err::Error;
+    use crate::types::{ChatMessage, Role};
+
+    #[test]
+    fn test_history_tree() {
+        let first = ChatMessage::new(Role::System, "System");
+        let question = ChatMessage::new(Role::User, "Question");
+        let reply = ChatMessage::new(Role::Assistant, "Reply");
+        let other = ChatMessage::new(Role::Assistant, "Other reply");
+
+        let mut tree = HistoryTree::default();
+        tree.record(&[first.clone(), question.clone(), reply.clone()]);
+        tree.record(&[first.clone(), question.clone(), other.clone()]);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.alternatives(2), vec![2, 3]);
+        // There are no alternatives past the end of the active path, not even the children of its last message
+        assert!(tree.alternatives(3).is_empty());
+        assert_eq!(tree.active(), [0, 1, 3]);
+
+        // Recording an already known path does not duplicate it
+        tree.record(&[first.clone(), question.clone(), reply.clone()]);
+        assert_eq!(tree.len(), 4);
+
+        assert_eq!(tree.switch(1).unwrap(), [first, question, other]);
+        assert!(matches!(tree.switch(4), Err(Error::UnknownBranch(4))));
+    }
+
+    #[test]
+    fn test_conversation_branches() {
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Question"));
+        conversation
+            .history
+            .push(ChatMessage::new(Role::Assistant, "Reply"));
+
+        conversation.fork_at(1).unwrap();
+        assert_eq!(conversation.history.len(), 2);
+        conversation
+            .history
+            .push(ChatMessage::new(Role::Assistant, "Other reply"));
+
+        let alternatives: Vec<usize> = conversation
+            .alternatives(2)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(alternatives.len(), 2);
+        conversation.switch_branch(alternatives[0]).unwrap();
+        assert_eq!(conversation.history[2].content, "Reply");
+        conversation.switch_branch(alternatives[1]).unwrap();
+        assert_eq!(conversation.history[2].content, "Other reply");
+
+        assert!(matches!(
+            conversation.fork_at(3),
+            Err(Error::HistoryIndexOutOfBounds { index: 3, len: 3 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        use crate::retrieval::{InMemoryIndex, RetrievalPolicy};
+
+        let index = InMemoryIndex::new(|_: &str| vec![1.0]);
+        index.add_documents([("a.md", "A")]).await.unwrap();
+        let context = RetrievalPolicy::new(index)
+            .context_message("A")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation.history.extend([
+            ChatMessage::new(Role::User, "First"),
+            ChatMessage::new(Role::Assistant, "First reply"),
+            context,
+            ChatMessage::new(Role::User, "Call a function"),
+            ChatMessage::new(Role::Assistant, ""),
+            ChatMessage::new(Role::Function, "{}"),
+            ChatMessage::new(Role::Assistant, "Second reply"),
+        ]);
+
+        // The whole turn is removed, including the context and the function call chain
+        let last = conversation.rollback().unwrap();
+        assert_eq!(last.content, "Second reply");
+        assert_eq!(conversation.history.len(), 3);
+        assert_eq!(conversation.rollback().unwrap().content, "First reply");
+        assert_eq!(conversation.history.len(), 1);
+        assert_eq!(conversation.rollback(), None);
+        assert_eq!(conversation.history.len(), 1);
+
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello"));
+        assert!(matches!(
+            conversation.rollback_to(2),
+            Err(Error::HistoryIndexOutOfBounds { index: 2, len: 2 })
+        ));
+        assert_eq!(conversation.rollback_to(1).unwrap()[0].content, "Hello");
+        assert_eq!(conversation.history.len(), 1);
+    }
+
+    #[test]
+    fn test_commit_choice() {
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Question"));
+        let choices = vec![
+            ChatMessage::new(Role::Assistant, "First"),
+            ChatMessage::new(Role::Assistant, "Second"),
+        ];
+        assert!(matches!(
+            conversation.commit_choice(choices.clone(), 2),
+            Err(Error::InvalidChoice { index: 2, count: 2 })
+        ));
+
+        conversation.commit_choice(choices, 1).unwrap();
+        assert_eq!(conversation.history.len(), 3);
+        assert_eq!(conversation.history[2].content, "Second");
+        let alternatives: Vec<String> = conversation
+            .alternatives(2)
+            .unwrap()
+            .into_iter()
+            .map(|(_, message)| message.content)
+            .collect();
+        assert_eq!(alternatives, ["First", "Second"]);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::client::{with_retries, ChatGPT};
+use crate::config::RequestOptions;
+use crate::types::{ChatMessage, CompletionResponse};
+
+type ProgressCallback = Arc<dyn Fn(&BulkProgress) + Send + Sync>;
+
+/// Progress of a [`ChatGPT::complete_many()`] call, passed to the progress callback after each item
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BulkProgress {
+    /// Total amount of items, including skipped ones
+    pub total: usize,
+    /// Amount of successfully completed items
+    pub completed: usize,
+    /// Amount of items that failed after all retries
+    pub failed: usize,
+    /// Amount of items skipped because they were already completed according to the checkpoint
+    pub skipped: usize,
+}
+
+impl BulkProgress {
+    /// Amount of items not processed yet
+    pub fn remaining(&self) -> usize {
+        self.total - self.completed - self.failed - self.skipped
+    }
+}
+
+/// Options for [`ChatGPT::complete_many_with_options()`]
+#[derive(Clone)]
+pub struct BulkOptions {
+    concurrency: usize,
+    max_retries: u32,
+    retry_delay: Duration,
+    checkpoint: Option<PathBuf>,
+    progress: Option<ProgressCallback>,
+    request_options: RequestOptions,
+}
+
+impl BulkOptions {
+    /// Constructs new options with the provided amount of concurrent requests, retrying each item up to 3 times
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+            checkpoint: None,
+            progress: None,
+            request_options: RequestOptions::default(),
+        }
+    }
+
+    /// Sets how often a failed item is retried, if the error is [retryable](crate::err::Error::is_retryable)
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, which doubles with each further retry.
+    ///
+    /// The delay requested by the API is used instead when rate limited.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Sets a file the indices of completed items are recorded in.
+    ///
+    /// Items recorded in an existing checkpoint file are skipped, so a crashed job can resume.
+    /// Recording is best effort, failing to write the checkpoint does not fail the item.
+    pub fn checkpoint<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// Sets a callback, which is called with the progress after each item
+    pub fn on_progress<F: Fn(&BulkProgress) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the options applied to each request
+    pub fn request_options(mut self, options: RequestOptions) -> Self {
+        self.request_options = options;
+        self
+    }
+}
+
+impl Debug for BulkOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BulkOptions")
+            .field("concurrency", &self.concurrency)
+            .field("max_retries", &self.max_retries)
+            .field("retry_delay", &self.retry_delay)
+            .field("checkpoint", &self.checkpoint)
+            .field("request_options", &self.request_options)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opens a checkpoint file for appending, returning the indices of completed items.
+///
+/// An unterminated last line, left by a crash, is cut off, so new records do not join onto it
+async fn open_checkpoint(path: &Path) -> crate::Result<(HashSet<usize>, File)> {
+    let content = read_checkpoint_file(path).await?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let complete = content.rfind('\n').map_or(0, |end| end + 1);
+    if complete < content.len() {
+        file.set_len(complete as u64).await?;
+    }
+    Ok((parse_checkpoint(&content), file))
+}
+
+async fn read_checkpoint_file(path: &Path) -> crate::Result<String> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Parses the indices of completed items, ignoring an unterminated last line
+fn parse_checkpoint(content: &str) -> HashSet<usize> {
+    content
+        .split_inclusive('\n')
+        .filter_map(|line| line.strip_suffix('\n'))
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
+
+/// Shared state of a running [`ChatGPT::complete_many()`] call
+struct BulkState {
+    progress: Mutex<BulkProgress>,
+    checkpoint: Option<tokio::sync::Mutex<File>>,
+}
+
+impl BulkState {
+    async fn record(&self, index: usize, success: bool, options: &BulkOptions) {
+        if success {
+            if let Some(checkpoint) = &self.checkpoint {
+                let mut file = checkpoint.lock().await;
+                if file
+                    .write_all(format!("{index}\n").as_bytes())
+                    .await
+                    .is_ok()
+                {
+                    let _ = file.flush().await;
+                }
+            }
+        }
+        let progress = {
+            let mut progress = self.progress.lock().unwrap();
+            if success {
+                progress.completed += 1;
+            } else {
+                progress.failed += 1;
+            }
+            *progress
+        };
+        if let Some(callback) = &options.progress {
+            callback(&progress);
+        }
+    }
+}
+
+impl ChatGPT {
+    /// Completes many histories with bounded concurrency, returning a stream of `(index, result)` pairs in the order of the histories.
+    ///
+    /// Failed items are retried up to 3 times, see [`BulkOptions`] for more options.
+    ///
+    /// Requires the `streams` crate feature
+    pub async fn complete_many<I>(
+        &self,
+        histories: I,
+        concurrency: usize,
+    ) -> crate::Result<impl Stream<Item = (usize, crate::Result<CompletionResponse>)> + '_>
+    where
+        I: IntoIterator<Item = Vec<ChatMessage>>,
+    {
+        self.complete_many_with_options(histories, BulkOptions::new(concurrency))
+            .await
+    }
+
+    /// Completes many histories with the provided options, returning a stream of `(index, result)` pairs in the order of the histories.
+    ///
+    /// Items already completed according to the checkpoint file are skipped and not returned.
+    ///
+    /// Requires the `streams` crate feature
+    pub async fn complete_many_with_options<I>(
+        &self,
+        histories: I,
+        options: BulkOptions,
+    ) -> crate::Result<impl Stream<Item = (usize, crate::Result<CompletionResponse>)> + '_>
+    where
+        I: IntoIterator<Item = Vec<ChatMessage>>,
+    {
+        let histories: Vec<Vec<ChatMessage>> = histories.into_iter().collect();
+        let (done, checkpoint) = match &options.checkpoint {
+            Some(path) => {
+                let (done, file) = open_checkpoint(path).await?;
+                (done, Some(tokio::sync::Mutex::new(file)))
+            }
+            None => (HashSet::new(), None),
+        };
+        let skipped = (0..histories.len())
+            .filter(|index| done.contains(index))
+            .count();
+        let state = Arc::new(BulkState {
+            progress: Mutex::new(BulkProgress {
+                total: histories.len(),
+                skipped,
+                ..Default::default()
+            }),
+            checkpoint,
+        });
+        let options = Arc::new(options);
+        let concurrency = options.concurrency;
+
+        Ok(futures_util::stream::iter(
+            histories
+                .into_iter()
+                .enumerate()
+                .filter(move |(index, _)| !done.contains(index)),
+        )
+        .map(move |(index, history)| {
+            let state = state.clone();
+            let options = options.clone();
+            async move {
+                let result = self.complete_with_retries(&history, &options).await;
+                state.record(index, result.is_ok(), &options).await;
+                (index, result)
+            }
+        })
+        .buffered(concurrency))
+    }
+
+    async fn complete_with_retries(
+        &self,
+        history: &Vec<ChatMessage>,
+        options: &BulkOptions,
+    ) -> crate::Result<CompletionResponse> {
+        let request = self.completion_request(history, false);
+        with_retries(options.max_retries, options.retry_delay, || {
+            self.complete(&request, &options.request_options)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::bulk::{open_checkpoint, parse_checkpoint};
+
+    #[test]
+    fn test_parse_checkpoint() {
+        assert!(parse_checkpoint("").is_empty());
+        assert_eq!(parse_checkpoint("0\n2\n1\n").len(), 3);
+        // A truncated last line is ignored, even if it parses
+        assert_eq!(parse_checkpoint("0\n1"), [0].into());
+    }
+
+    #[tokio::test]
+    async fn test_read_checkpoint() {
+        let path = std::env::temp_dir().join(format!("chatgpt-checkpoint-{}", std::process::id()));
+        let (done, _) = open_checkpoint(&path).await.unwrap();
+        assert!(done.is_empty());
+
+        // Opening cuts the truncated line off, so appended records stay separate
+        std::fs::write(&path, "0\n1").unwrap();
+        let (done, mut file) = open_checkpoint(&path).await.unwrap();
+        assert_eq!(done, [0].into());
+        file.write_all(b"2\n").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0\n2\n");
+        let (done, _) = open_checkpoint(&path).await.unwrap();
+        assert_eq!(done, [0, 2].into());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
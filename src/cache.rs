@@ -0,0 +1,535 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "streams")]
+use crate::types::ResponseChunk;
+use crate::types::{CompletionRequest, CompletionResponse, ResponseMetadata};
+
+/// A cached completion response
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Unix seconds timestamp of when the response was stored
+    pub stored_at: u64,
+    /// The endpoint and the serialized request the response was stored for, see [`CacheKey::request`]
+    #[serde(default)]
+    pub request: String,
+    /// The cached response
+    pub response: CompletionResponse,
+    /// Metadata of the HTTP response the cached response was received with, which is not part of the serialized response
+    #[serde(default)]
+    pub metadata: Option<ResponseMetadata>,
+}
+
+/// The key of a cacheable request, computed by [`ResponseCache::key()`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Hash of the endpoint, the credentials and the request, under which the response is stored in the backend
+    pub hash: String,
+    /// The endpoint, a hash of the credentials and the serialized request,
+    /// compared with the stored entry to rule out hash collisions
+    pub request: String,
+}
+
+/// Storage for cached completion responses, keyed by a hash of the request
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Returns the entry stored under the key, if any
+    async fn get(&self, key: &str) -> crate::Result<Option<CacheEntry>>;
+    /// Stores the entry under the key, replacing any previous entry
+    async fn put(&self, key: &str, entry: CacheEntry) -> crate::Result<()>;
+    /// Removes the entry stored under the key, if any
+    async fn remove(&self, key: &str) -> crate::Result<()>;
+}
+
+/// An in-memory cache backend, evicting the least recently used entries once the capacity is reached
+#[derive(Debug)]
+pub struct MemoryCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Debug, Default)]
+struct LruState {
+    entries: HashMap<String, (CacheEntry, u64)>,
+    usage: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl LruState {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((_, last_used)) = self.entries.get_mut(key) {
+            self.usage.remove(last_used);
+            *last_used = tick;
+            self.usage.insert(tick, key.to_string());
+        }
+    }
+}
+
+impl MemoryCache {
+    /// Constructs a new cache holding up to `capacity` responses
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    /// Returns the amount of cached responses
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Checks whether no responses are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> crate::Result<Option<CacheEntry>> {
+        let mut state = self.state.lock().unwrap();
+        state.touch(key);
+        Ok(state.entries.get(key).map(|(entry, _)| entry.clone()))
+    }
+
+    async fn put(&self, key: &str, entry: CacheEntry) -> crate::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some((_, last_used)) = state.entries.remove(key) {
+            state.usage.remove(&last_used);
+        }
+        while state.entries.len() >= self.capacity {
+            let Some((_, evicted)) = state.usage.pop_first() else {
+                break;
+            };
+            state.entries.remove(&evicted);
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(key.to_string(), (entry, tick));
+        state.usage.insert(tick, key.to_string());
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> crate::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some((_, last_used)) = state.entries.remove(key) {
+            state.usage.remove(&last_used);
+        }
+        Ok(())
+    }
+}
+
+/// An on-disk cache backend, storing each response as a JSON file in a directory
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    directory: PathBuf,
+}
+
+impl DiskCache {
+    /// Constructs a new cache in the directory, which is created if it does not exist
+    pub fn new<P: Into<PathBuf>>(directory: P) -> crate::Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{key}.json"))
+    }
+}
+
+#[async_trait]
+impl CacheBackend for DiskCache {
+    async fn get(&self, key: &str) -> crate::Result<Option<CacheEntry>> {
+        match tokio::fs::read(self.path(key)).await {
+            // Unreadable entries, e.g. from older versions, are treated as missing
+            Ok(data) => Ok(serde_json::from_slice(&data).ok()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, entry: CacheEntry) -> crate::Result<()> {
+        crate::converse::write_atomic(&self.path(key), &serde_json::to_vec(&entry)?).await
+    }
+
+    async fn remove(&self, key: &str) -> crate::Result<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An opt-in cache of completion responses, enabled with [`ChatGPTBuilder::cache()`](crate::client::ChatGPTBuilder::cache).
+///
+/// Requests are keyed by a hash of their endpoint, credentials, model, messages, sampling parameters and functions,
+/// so clients with different API keys or organizations do not share entries. Responses of fallback engines are stored
+/// for the engine that produced them.
+/// Cached entries are only used if their full request matches, so hash collisions are never served.
+/// Requests with a non-zero temperature are not cached unless forced, as their responses are expected to vary.
+/// Only non-streamed responses are stored, but streamed requests replay them from the cache.
+/// Storing a response is best effort, failing to store it does not fail the request.
+#[derive(Clone)]
+pub struct ResponseCache {
+    backend: Arc<dyn CacheBackend>,
+    ttl: Option<Duration>,
+    force: bool,
+}
+
+impl ResponseCache {
+    /// Constructs a new cache with the provided backend, without expiration
+    pub fn new<B: CacheBackend + 'static>(backend: B) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            ttl: None,
+            force: false,
+        }
+    }
+
+    /// Constructs a new in-memory cache holding up to `capacity` responses
+    pub fn in_memory(capacity: usize) -> Self {
+        Self::new(MemoryCache::new(capacity))
+    }
+
+    /// Sets for how long cached responses are used
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Also caches requests with a non-zero temperature
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Computes the cache key of the request sent to the endpoint with the credentials, e.g. the authentication headers,
+    /// or `None` if the request should not be cached. Only a hash of the credentials is part of the key.
+    pub fn key(
+        &self,
+        endpoint: &Url,
+        credentials: &[u8],
+        request: &CompletionRequest<'_>,
+    ) -> crate::Result<Option<CacheKey>> {
+        if request.temperature != 0.0 && !self.force {
+            return Ok(None);
+        }
+        // Streamed and non-streamed requests share their entries
+        let request = CompletionRequest {
+            stream: false,
+            ..request.clone()
+        };
+        let request = format!(
+            "{endpoint} {:032x} {}",
+            fnv1a(credentials),
+            serde_json::to_string(&request)?
+        );
+        Ok(Some(CacheKey {
+            hash: format!("{:032x}", fnv1a(request.as_bytes())),
+            request,
+        }))
+    }
+
+    /// Returns the cached response, if it did not expire yet
+    pub async fn get(&self, key: &CacheKey) -> crate::Result<Option<CompletionResponse>> {
+        let Some(entry) = self.backend.get(&key.hash).await? else {
+            return Ok(None);
+        };
+        if entry.request != key.request {
+            return Ok(None);
+        }
+        let expired = self
+            .ttl
+            .is_some_and(|ttl| now().saturating_sub(entry.stored_at) >= ttl.as_secs());
+        if expired {
+            self.backend.remove(&key.hash).await?;
+            return Ok(None);
+        }
+        Ok(Some(CompletionResponse {
+            metadata: entry.metadata,
+            ..entry.response
+        }))
+    }
+
+    /// Stores the response in the cache
+    pub async fn put(&self, key: &CacheKey, response: &CompletionResponse) -> crate::Result<()> {
+        self.backend
+            .put(
+                &key.hash,
+                CacheEntry {
+                    stored_at: now(),
+                    request: key.request.clone(),
+                    response: response.clone(),
+                    metadata: response.metadata.clone(),
+                },
+            )
+            .await
+    }
+}
+
+impl Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .field("force", &self.force)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Replays a cached response as the chunks of a streamed response
+#[cfg(feature = "streams")]
+pub(crate) fn replay_chunks(response: CompletionResponse) -> Vec<ResponseChunk> {
+    let response = mark_cached(response);
+    let mut chunks = vec![ResponseChunk::Metadata(
+        response.metadata.clone().unwrap_or_default(),
+    )];
+    for choice in response.message_choices {
+        chunks.push(ResponseChunk::BeginResponse {
+            role: choice.message.role,
+            response_index: choice.index as usize,
+        });
+        chunks.push(ResponseChunk::Content {
+            delta: choice.message.content,
+            response_index: choice.index as usize,
+        });
+        chunks.push(ResponseChunk::CloseResponse {
+            response_index: choice.index as usize,
+        });
+    }
+    chunks.push(ResponseChunk::Done);
+    chunks
+}
+
+/// Marks the response as served from the cache
+pub(crate) fn mark_cached(response: CompletionResponse) -> CompletionResponse {
+    CompletionResponse {
+        metadata: Some(ResponseMetadata {
+            cached: true,
+            ..response.metadata.clone().unwrap_or_default()
+        }),
+        ..response
+    }
+}
+
+/// 128-bit FNV-1a hash, which is stable across platforms and versions
+fn fnv1a(data: &[u8]) -> u128 {
+    data.iter()
+        .fold(0x6c62272e07bb014262b821756295c58d, |hash, byte| {
+            (hash ^ *byte as u128).wrapping_mul(0x0000000001000000000000000000013b)
+        })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::cache::{CacheBackend, CacheEntry, CacheKey, DiskCache, MemoryCache, ResponseCache};
+    use crate::client::ChatGPT;
+    use crate::config::ModelConfigurationBuilder;
+    use crate::types::{
+        ChatMessage, CompletionResponse, MessageChoice, ResponseMetadata, Role, TokenUsage,
+    };
+
+    fn response(content: &str) -> CompletionResponse {
+        CompletionResponse {
+            message_id: Some("chatcmpl-1".to_string()),
+            created_timestamp: Some(1700000000),
+            model: "gpt-3.5-turbo".to_string(),
+            usage: TokenUsage {
+                prompt_tokens: 1,
+                completion_tokens: 1,
+                total_tokens: 2,
+            },
+            message_choices: vec![MessageChoice {
+                message: ChatMessage::new(Role::Assistant, content),
+                finish_reason: "stop".to_string(),
+                index: 0,
+            }],
+            metadata: None,
+        }
+    }
+
+    fn client(cache: ResponseCache, temperature: f32) -> ChatGPT {
+        ChatGPT::builder()
+            .api_key("key")
+            .config(
+                ModelConfigurationBuilder::default()
+                    .temperature(temperature)
+                    .build()
+                    .unwrap(),
+            )
+            .cache(cache)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let cache = MemoryCache::new(2);
+        let entry = |content| CacheEntry {
+            stored_at: 0,
+            request: String::new(),
+            response: response(content),
+            metadata: None,
+        };
+        cache.put("a", entry("a")).await.unwrap();
+        cache.put("b", entry("b")).await.unwrap();
+        // Using `a` makes `b` the least recently used entry
+        assert!(cache.get("a").await.unwrap().is_some());
+        cache.put("c", entry("c")).await.unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("b").await.unwrap().is_none());
+        assert!(cache.get("a").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache() {
+        let directory = std::env::temp_dir().join(format!("chatgpt-cache-{}", std::process::id()));
+        let key = |hash: &str, request: &str| CacheKey {
+            hash: hash.to_string(),
+            request: request.to_string(),
+        };
+        let cache = ResponseCache::new(DiskCache::new(&directory).unwrap());
+        cache
+            .put(&key("key", "request"), &response("Hello"))
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get(&key("key", "request")).await.unwrap(),
+            Some(response("Hello"))
+        );
+        assert_eq!(cache.get(&key("missing", "request")).await.unwrap(), None);
+        // The metadata of the original response is kept on disk
+        let metadata = ResponseMetadata {
+            request_id: Some("req-1".to_string()),
+            ..Default::default()
+        };
+        let with_metadata = CompletionResponse {
+            metadata: Some(metadata),
+            ..response("Hello")
+        };
+        cache
+            .put(&key("metadata", "request"), &with_metadata)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get(&key("metadata", "request")).await.unwrap(),
+            Some(with_metadata)
+        );
+        // A colliding hash of another request is not served
+        assert_eq!(cache.get(&key("key", "other")).await.unwrap(), None);
+
+        let expiring = ResponseCache::new(DiskCache::new(&directory).unwrap()).ttl(Duration::ZERO);
+        assert_eq!(expiring.get(&key("key", "request")).await.unwrap(), None);
+        assert!(!directory.join("key.json").exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cached_completion() {
+        let history = vec![ChatMessage::new(Role::User, "Hello")];
+
+        let cache = ResponseCache::in_memory(16);
+        let client = client(cache.clone(), 0.0);
+        let key = client
+            .cache_key(&cache, &client.completion_request(&history, true))
+            .unwrap()
+            .unwrap();
+        cache.put(&key, &response("Hi")).await.unwrap();
+
+        // Requests to another endpoint do not share entries
+        let endpoint = reqwest::Url::parse("http://localhost:8080/v1/chat/completions").unwrap();
+        let other = cache
+            .key(&endpoint, b"", &client.completion_request(&history, true))
+            .unwrap()
+            .unwrap();
+        assert_ne!(other.hash, key.hash);
+        assert_eq!(cache.get(&other).await.unwrap(), None);
+
+        // Neither do clients with other credentials
+        let other_key = ChatGPT::builder()
+            .api_key("other")
+            .config(client.config.clone())
+            .build()
+            .unwrap();
+        let other = other_key
+            .cache_key(&cache, &client.completion_request(&history, true))
+            .unwrap()
+            .unwrap();
+        assert_ne!(other.hash, key.hash);
+        assert!(!other.request.contains("other"));
+
+        // Served from the cache without reaching the API
+        let cached = client.send_history(&history).await.unwrap();
+        assert_eq!(cached.message().content, "Hi");
+        assert!(cached.metadata.unwrap().cached);
+
+        // Requests with a non-zero temperature bypass the cache unless forced
+        let random = crate::client::ChatGPT::builder()
+            .api_key("key")
+            .build()
+            .unwrap();
+        assert_eq!(
+            cache
+                .key(
+                    &random.config.api_url,
+                    b"",
+                    &random.completion_request(&history, false)
+                )
+                .unwrap(),
+            None
+        );
+        assert!(cache
+            .clone()
+            .force(true)
+            .key(
+                &random.config.api_url,
+                b"",
+                &random.completion_request(&history, false)
+            )
+            .unwrap()
+            .is_some());
+    }
+
+    #[cfg(feature = "streams")]
+    #[tokio::test]
+    async fn test_streamed_replay() {
+        use futures::StreamExt;
+
+        let history = vec![ChatMessage::new(Role::User, "Hello")];
+        let cache = ResponseCache::in_memory(16);
+        let client = client(cache.clone(), 0.0);
+        let key = client
+            .cache_key(&cache, &client.completion_request(&history, false))
+            .unwrap()
+            .unwrap();
+        cache.put(&key, &response("Hi")).await.unwrap();
+
+        let chunks: Vec<_> = client
+            .send_history_streaming(&history)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let messages = ChatMessage::from_response_chunks(chunks);
+        assert_eq!(messages, vec![ChatMessage::new(Role::Assistant, "Hi")]);
+    }
+}
@@ -2,19 +2,49 @@
 #![doc = include_str!("../README.md")]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+/// Speech to text and text to speech
+pub mod audio;
+/// Files and Batch API for bulk offline completions
+#[cfg(feature = "json")]
+pub mod batch;
+/// Branching conversation histories
+pub mod branch;
+/// Concurrent completion of many histories
+#[cfg(feature = "streams")]
+pub mod bulk;
+/// Caching of completion responses
+#[cfg(feature = "json")]
+pub mod cache;
 /// This module contains the ChatGPT client
 pub mod client;
 /// This module contains additional configuration for ChatGPT
 pub mod config;
 /// Conversation related types
 pub mod converse;
+/// Embeddings and vector helpers
+pub mod embeddings;
 /// This module contains the errors related to the API
 pub mod err;
 #[cfg(feature = "functions")]
 /// Contains API for function calling
 pub mod functions;
+/// Image generation and editing
+pub mod images;
+/// Content moderation
+pub mod moderation;
+/// Load balancing between multiple clients
+pub mod pool;
 /// The prelude module. Import everything from it to get the necessary elements from this library
 pub mod prelude;
+/// Client-side rate limiting
+pub mod ratelimit;
+/// Retrieval-augmented conversations
+pub mod retrieval;
+/// Versioned snapshots of the full conversation state
+pub mod snapshot;
+/// Persistent storage of conversations
+#[cfg(feature = "json")]
+pub mod store;
 /// Types returned from the API and sent to it
 pub mod types;
 
@@ -22,7 +52,7 @@ pub mod types;
 pub type Result<T> = std::result::Result<T, err::Error>;
 
 #[cfg(test)]
-pub mod test {
+mod test {
     use std::path::Path;
 
     use futures::StreamExt;
@@ -39,6 +69,33 @@ pub mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_list_models() -> crate::Result<()> {
+        let client = ChatGPT::new(std::env::var("TEST_API_KEY")?)?;
+        let models = client.list_models().await?;
+        assert!(!models.is_empty());
+        let model = client.retrieve_model(&models[0].id).await?;
+        assert_eq!(model.id, models[0].id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_moderation() -> crate::Result<()> {
+        let client = ChatGPT::new(std::env::var("TEST_API_KEY")?)?;
+        let result = client.moderate("I want to hug my cat").await?;
+        assert!(!result.flagged);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embeddings() -> crate::Result<()> {
+        let client = ChatGPT::new(std::env::var("TEST_API_KEY")?)?;
+        let response = client.embed(["Rust", "Crab"]).await?;
+        assert_eq!(response.data.len(), 2);
+        assert!(response.data[0].cosine_similarity(&response.data[1]) > 0.0);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_undirected_conversation() -> crate::Result<()> {
         let client = ChatGPT::new(std::env::var("TEST_API_KEY")?)?;
@@ -93,14 +150,14 @@ pub mod test {
 
     #[tokio::test]
     async fn test_some_config() -> crate::Result<()> {
-        let client = ChatGPT::new_with_config(
-            std::env::var("TEST_API_KEY")?,
-            ModelConfiguration {
+        let client = ChatGPT::builder()
+            .api_key(std::env::var("TEST_API_KEY")?)
+            .config(ModelConfiguration {
                 temperature: 0.9,
                 reply_count: 3,
                 ..Default::default()
-            },
-        )?;
+            })
+            .build()?;
         let response = client
             .send_message("Could you give me names of three popular Rust web frameworks?")
             .await?;
@@ -136,13 +193,13 @@ pub mod test {
 
     #[tokio::test]
     async fn test_max_token_config() -> crate::Result<()> {
-        let client = ChatGPT::new_with_config(
-            std::env::var("TEST_API_KEY")?,
-            ModelConfiguration {
+        let client = ChatGPT::builder()
+            .api_key(std::env::var("TEST_API_KEY")?)
+            .config(ModelConfiguration {
                 max_tokens: Some(10),
                 ..Default::default()
-            },
-        )?;
+            })
+            .build()?;
         let response = client
             .send_message("Could you give me names of three popular Rust web frameworks?")
             .await?;
@@ -155,12 +212,12 @@ pub mod test {
 
     #[tokio::test]
     async fn test_default_max_token_config() -> crate::Result<()> {
-        let client = ChatGPT::new_with_config(
-            std::env::var("TEST_API_KEY")?,
-            ModelConfiguration {
+        let client = ChatGPT::builder()
+            .api_key(std::env::var("TEST_API_KEY")?)
+            .config(ModelConfiguration {
                 ..Default::default()
-            },
-        )?;
+            })
+            .build()?;
         let response = client
             .send_message("Could you give me names of three popular Rust web frameworks?")
             .await?;
@@ -1,83 +1,188 @@
-use std::path::Path;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use reqwest::header::AUTHORIZATION;
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{self, Proxy};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::tls::{Certificate, Version};
+use reqwest::{self, Method, Proxy, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use url::Url;
 
-#[cfg(feature = "streams")]
-use reqwest::Response;
 #[cfg(feature = "streams")]
 use {
-    crate::types::InboundChunkPayload, crate::types::InboundResponseChunk,
-    crate::types::ResponseChunk, futures_util::Stream,
+    crate::config::CancellationToken,
+    crate::types::{AbortReason, InboundChunkPayload, InboundResponseChunk, ResponseChunk},
+    futures_util::Stream,
+    tokio::time::Instant,
 };
 
-use crate::config::ModelConfiguration;
+#[cfg(feature = "json")]
+use crate::cache::{CacheKey, ResponseCache};
+use crate::config::{ChatGPTEngine, ModelConfiguration, RequestOptions};
 use crate::converse::Conversation;
-use crate::types::{ChatMessage, CompletionRequest, CompletionResponse, Role, ServerResponse};
+use crate::err::{ApiError, Error};
+use crate::pool::ClientPool;
+use crate::ratelimit::{RateLimit, RateLimiter};
+#[cfg(feature = "json")]
+use crate::store::{ConversationStore, Journal};
+use crate::types::{
+    ChatMessage, CompletionError, CompletionRequest, CompletionResponse, Model, ModelList,
+    ResponseMetadata, Role,
+};
 
 #[cfg(feature = "functions")]
 use crate::functions::{FunctionArgument, FunctionDescriptor};
 
+/// Function list sent with requests that do not provide any functions
+#[cfg(feature = "functions")]
+static NO_FUNCTIONS: Vec<serde_json::Value> = Vec::new();
+
 /// The client that operates the ChatGPT API
 #[derive(Debug, Clone)]
 pub struct ChatGPT {
-    client: reqwest::Client,
+    pub(crate) client: reqwest::Client,
+    headers: HeaderMap,
+    limiter: Option<Arc<RateLimiter>>,
+    pool: Option<Arc<ClientPool>>,
+    #[cfg(feature = "json")]
+    cache: Option<Arc<ResponseCache>>,
     /// The configuration for this ChatGPT client
     pub config: ModelConfiguration,
 }
 
 impl ChatGPT {
+    /// Creates a new [`ChatGPTBuilder`], which allows fine-grained configuration of the client
+    pub fn builder() -> ChatGPTBuilder {
+        ChatGPTBuilder::default()
+    }
+
+    /// Constructs a new ChatGPT API client from the environment variables.
+    ///
+    /// See [`ChatGPTBuilder::from_env()`] for the variables that are read.
+    pub fn from_env() -> crate::Result<Self> {
+        ChatGPTBuilder::from_env()?.build()
+    }
+
     /// Constructs a new ChatGPT API client with provided API key and default configuration
     pub fn new<S: Into<String>>(api_key: S) -> crate::Result<Self> {
-        Self::new_with_config(api_key, ModelConfiguration::default())
+        Self::builder().api_key(api_key).build()
     }
 
     /// Constructs a new ChatGPT API client with provided API key, default configuration and a reqwest proxy
+    #[deprecated(since = "1.3.0", note = "use `ChatGPT::builder()` instead")]
     pub fn new_with_proxy<S: Into<String>>(api_key: S, proxy: Proxy) -> crate::Result<Self> {
-        Self::new_with_config_proxy(api_key, ModelConfiguration::default(), proxy)
+        Self::builder().api_key(api_key).proxy(proxy).build()
     }
 
     /// Constructs a new ChatGPT API client with provided API Key and Configuration
+    #[deprecated(since = "1.3.0", note = "use `ChatGPT::builder()` instead")]
     pub fn new_with_config<S: Into<String>>(
         api_key: S,
         config: ModelConfiguration,
     ) -> crate::Result<Self> {
-        let api_key = api_key.into();
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_bytes(format!("Bearer {api_key}").as_bytes())?,
-        );
-        let client = reqwest::ClientBuilder::new()
-            .default_headers(headers)
-            .timeout(config.timeout)
-            .build()?;
-        Ok(Self { client, config })
+        Self::builder().api_key(api_key).config(config).build()
     }
 
     /// Constructs a new ChatGPT API client with provided API Key, Configuration and Reqwest proxy
+    #[deprecated(since = "1.3.0", note = "use `ChatGPT::builder()` instead")]
     pub fn new_with_config_proxy<S: Into<String>>(
         api_key: S,
         config: ModelConfiguration,
         proxy: Proxy,
     ) -> crate::Result<Self> {
-        let api_key = api_key.into();
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_bytes(format!("Bearer {api_key}").as_bytes())?,
-        );
-
-        let client = reqwest::ClientBuilder::new()
-            .default_headers(headers)
-            .timeout(config.timeout)
+        Self::builder()
+            .api_key(api_key)
+            .config(config)
             .proxy(proxy)
-            .build()?;
-        Ok(Self { client, config })
+            .build()
+    }
+
+    /// Prepares a request to the provided URL with authentication headers and request timeout applied
+    pub(crate) fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .headers(self.headers.clone())
+            .timeout(self.config.timeout)
+    }
+
+    /// Makes this client distribute its completion requests through the pool
+    pub(crate) fn with_pool(self, pool: Arc<ClientPool>) -> Self {
+        Self {
+            limiter: None,
+            pool: Some(pool),
+            ..self
+        }
+    }
+
+    /// Resolves an API endpoint, e.g. `models`, relative to the base URL of the API.
+    ///
+    /// The base URL is derived from the `api_url` of the configuration, which points to the `chat/completions` endpoint.
+    pub(crate) fn endpoint(&self, path: &str) -> crate::Result<Url> {
+        self.config
+            .api_url
+            .join("../")
+            .and_then(|base| base.join(path))
+            .map_err(|err| Error::ParsingError(format!("Failed to resolve API endpoint: {err}")))
+    }
+
+    /// Lists all models available to the configured API key
+    pub async fn list_models(&self) -> crate::Result<Vec<Model>> {
+        let response = self
+            .request(Method::GET, self.endpoint("models")?)
+            .send()
+            .await?;
+        let list: ModelList = decode_response(response).await?;
+        Ok(list.data)
     }
+
+    /// Retrieves a single model by its ID, e.g. `gpt-4`
+    pub async fn retrieve_model<S: AsRef<str>>(&self, id: S) -> crate::Result<Model> {
+        let response = self
+            .request(
+                Method::GET,
+                self.endpoint(&format!("models/{}", id.as_ref()))?,
+            )
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Lists all models available to the configured API key, mapped onto [`ChatGPTEngine`]s.
+    ///
+    /// Note that this includes non-chat models (e.g. embedding models) as [`ChatGPTEngine::Custom`] engines,
+    /// you can use [`ChatGPTEngine::info()`] to only keep the known chat engines.
+    pub async fn list_engines(&self) -> crate::Result<Vec<ChatGPTEngine>> {
+        Ok(self
+            .list_models()
+            .await?
+            .iter()
+            .map(Model::engine)
+            .collect())
+    }
+
+    /// Loads a conversation from the store and attaches the store to it, or returns `None` if it does not exist.
+    ///
+    /// See [`Conversation::attach_store()`] for details.
+    #[cfg(feature = "json")]
+    pub async fn load_conversation<S: Into<String>>(
+        &self,
+        store: Arc<dyn ConversationStore>,
+        id: S,
+    ) -> crate::Result<Option<Conversation>> {
+        let id = id.into();
+        let Some(history) = store.load(&id).await? else {
+            return Ok(None);
+        };
+        let mut conversation = Conversation::new_with_history(self.clone(), history);
+        conversation.bind_store(store, id);
+        Ok(Some(conversation))
+    }
+
     /// Restores a conversation from local conversation JSON file.
     /// The conversation file can originally be saved using the [`Conversation::save_history_json()`].
     #[cfg(feature = "json")]
@@ -100,8 +205,28 @@ impl ChatGPT {
         ))
     }
 
+    /// Restores a conversation from a local JSON Lines journal, and keeps appending new messages to it.
+    /// The journal can originally be saved using the [`Conversation::save_history_journal()`].
+    ///
+    /// A truncated last line, e.g. left by a crash while appending, is dropped from the journal.
+    #[cfg(feature = "json")]
+    pub async fn restore_conversation_journal<P: AsRef<Path>>(
+        &self,
+        file: P,
+    ) -> crate::Result<Conversation> {
+        let journal = Journal::new(file.as_ref());
+        let history = journal.load("").await?.ok_or_else(|| {
+            crate::err::Error::ParsingError("Conversation journal file does not exist".to_string())
+        })?;
+        let mut conversation = Conversation::new_with_history(self.clone(), history);
+        conversation.bind_journal(journal);
+        Ok(conversation)
+    }
+
     /// Restores a conversation from local conversation postcard file.
     /// The conversation file can originally be saved using the [`Conversation::save_history_postcard()`].
+    ///
+    /// Files saved by versions without message metadata are supported as well.
     #[cfg(feature = "postcard")]
     pub async fn restore_conversation_postcard<P: AsRef<Path>>(
         &self,
@@ -118,7 +243,7 @@ impl ChatGPT {
         file.read_to_end(&mut buf).await?;
         Ok(Conversation::new_with_history(
             self.clone(),
-            postcard::from_bytes(&buf)?,
+            crate::types::from_postcard(&buf)?,
         ))
     }
 
@@ -147,33 +272,20 @@ impl ChatGPT {
         &self,
         history: &Vec<ChatMessage>,
     ) -> crate::Result<CompletionResponse> {
-        let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
-                model: self.config.engine.as_ref(),
-                messages: history,
-                stream: false,
-                temperature: self.config.temperature,
-                top_p: self.config.top_p,
-                max_tokens: self.config.max_tokens,
-                frequency_penalty: self.config.frequency_penalty,
-                presence_penalty: self.config.presence_penalty,
-                reply_count: self.config.reply_count,
-                #[cfg(feature = "functions")]
-                functions: &Vec::new(),
-            })
-            .send()
-            .await?
-            .json()
-            .await?;
-        match response {
-            ServerResponse::Error { error } => Err(crate::err::Error::BackendError {
-                message: error.message,
-                error_type: error.error_type,
-            }),
-            ServerResponse::Completion(completion) => Ok(completion),
-        }
+        self.send_history_with_options(history, &RequestOptions::default())
+            .await
+    }
+
+    /// Explicitly sends whole message history to the API, applying the provided per-call options.
+    ///
+    /// Returns [`Error::Cancelled`] if the request was cancelled, and [`Error::Timeout`] if the deadline has expired.
+    pub async fn send_history_with_options(
+        &self,
+        history: &Vec<ChatMessage>,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse> {
+        self.complete(&self.completion_request(history, false), options)
+            .await
     }
 
     /// Explicitly sends whole message history to the API and returns the response as stream. **Stream will be empty** if
@@ -188,26 +300,66 @@ impl ChatGPT {
         &self,
         history: &Vec<ChatMessage>,
     ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
-        let response = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
-                model: self.config.engine.as_ref(),
-                stream: true,
-                messages: history,
-                temperature: self.config.temperature,
-                top_p: self.config.top_p,
-                max_tokens: self.config.max_tokens,
-                frequency_penalty: self.config.frequency_penalty,
-                presence_penalty: self.config.presence_penalty,
-                reply_count: self.config.reply_count,
-                #[cfg(feature = "functions")]
-                functions: &Vec::new(),
-            })
-            .send()
-            .await?;
+        self.send_history_streaming_with_options(history, &RequestOptions::default())
+            .await
+    }
 
-        Self::process_streaming_response(response)
+    /// Explicitly sends whole message history to the API and returns the response as stream, applying the provided per-call options.
+    ///
+    /// If the request is cancelled or times out after the response has started, the stream ends with a [`ResponseChunk::Aborted`] chunk.
+    ///
+    /// Requires the `streams` crate feature
+    #[cfg(feature = "streams")]
+    pub async fn send_history_streaming_with_options(
+        &self,
+        history: &Vec<ChatMessage>,
+        options: &RequestOptions,
+    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
+        let deadline = options.deadline_instant();
+        let request = self.completion_request(history, true);
+        #[cfg(feature = "json")]
+        if let Some(cache) = &self.cache {
+            if let Some(key) = self.cache_key(cache, &request)? {
+                if let Some(response) = cache.get(&key).await? {
+                    let chunks = crate::cache::replay_chunks(response);
+                    let replay: BoxedChunkStream = Box::pin(futures_util::stream::iter(chunks));
+                    return Ok(guard_stream(
+                        replay,
+                        options.cancellation.clone(),
+                        deadline,
+                        self.config.stream_idle_timeout,
+                    ));
+                }
+            }
+        }
+        let attempt = |engine: ChatGPTEngine| {
+            let request = &request;
+            async move {
+                let request = CompletionRequest {
+                    model: engine.as_ref(),
+                    ..request.clone()
+                };
+                let (response, mut metadata) =
+                    self.send_completion_request(&request, options).await?;
+                metadata.engine = Some(engine.clone());
+                if !response.status().is_success() {
+                    return Err(api_error(response, &metadata).await);
+                }
+                Ok(Self::process_streaming_response(response, metadata))
+            }
+        };
+        let stream = options
+            .run_until(deadline, self.with_fallback(attempt))
+            .await?;
+        let stream: BoxedChunkStream = Box::pin(stream);
+        Ok(guard_stream(
+            stream,
+            options.cancellation.clone(),
+            deadline,
+            options
+                .stream_idle_timeout
+                .unwrap_or(self.config.stream_idle_timeout),
+        ))
     }
 
     /// Sends a single message to the API without preserving message history.
@@ -215,38 +367,8 @@ impl ChatGPT {
         &self,
         message: S,
     ) -> crate::Result<CompletionResponse> {
-        let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
-                model: self.config.engine.as_ref(),
-                messages: &vec![ChatMessage {
-                    role: Role::User,
-                    content: message.into(),
-                    #[cfg(feature = "functions")]
-                    function_call: None,
-                }],
-                stream: false,
-                temperature: self.config.temperature,
-                top_p: self.config.top_p,
-                max_tokens: self.config.max_tokens,
-                frequency_penalty: self.config.frequency_penalty,
-                presence_penalty: self.config.presence_penalty,
-                reply_count: self.config.reply_count,
-                #[cfg(feature = "functions")]
-                functions: &Vec::new(),
-            })
-            .send()
-            .await?
-            .json()
-            .await?;
-        match response {
-            ServerResponse::Error { error } => Err(crate::err::Error::BackendError {
-                message: error.message,
-                error_type: error.error_type,
-            }),
-            ServerResponse::Completion(completion) => Ok(completion),
-        }
+        self.send_history(&vec![ChatMessage::new(Role::User, message)])
+            .await
     }
 
     /// Sends a single message to the API, and returns the response as stream, without preserving message history. **Stream will be empty** if
@@ -258,71 +380,192 @@ impl ChatGPT {
         &self,
         message: S,
     ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
-        let response = self
+        self.send_history_streaming(&vec![ChatMessage::new(Role::User, message)])
+            .await
+    }
+
+    /// Constructs a completion request for the provided history, using the parameters from the configuration
+    pub fn completion_request<'a>(
+        &'a self,
+        history: &'a Vec<ChatMessage>,
+        stream: bool,
+    ) -> CompletionRequest<'a> {
+        CompletionRequest {
+            model: self.config.engine.as_ref(),
+            messages: history,
+            stream,
+            temperature: self.config.temperature,
+            top_p: self.config.top_p,
+            max_tokens: self.config.max_tokens,
+            frequency_penalty: self.config.frequency_penalty,
+            presence_penalty: self.config.presence_penalty,
+            reply_count: self.config.reply_count,
+            #[cfg(feature = "functions")]
+            functions: &NO_FUNCTIONS,
+        }
+    }
+
+    /// Sends the completion request, waiting for the rate limiter first if one is configured.
+    ///
+    /// Streamed requests are only limited by the timeout until the response starts.
+    pub(crate) async fn send_completion_request(
+        &self,
+        request: &CompletionRequest<'_>,
+        options: &RequestOptions,
+    ) -> crate::Result<(Response, ResponseMetadata)> {
+        if let Some(pool) = &self.pool {
+            return pool.send_completion_request(request, options).await;
+        }
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(request.estimated_tokens()).await;
+        }
+        let builder = self
             .client
             .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
-                model: self.config.engine.as_ref(),
-                messages: &vec![ChatMessage {
-                    role: Role::User,
-                    content: message.into(),
-                    #[cfg(feature = "functions")]
-                    function_call: None,
-                }],
-                stream: true,
-                temperature: self.config.temperature,
-                top_p: self.config.top_p,
-                max_tokens: self.config.max_tokens,
-                frequency_penalty: self.config.frequency_penalty,
-                presence_penalty: self.config.presence_penalty,
-                reply_count: self.config.reply_count,
-                #[cfg(feature = "functions")]
-                functions: &Vec::new(),
+            .headers(self.headers.clone())
+            .json(request);
+        let timeout = options.deadline.unwrap_or(self.config.timeout);
+        let response = if request.stream {
+            tokio::time::timeout(timeout, builder.send())
+                .await
+                .map_err(|_| Error::Timeout)??
+        } else {
+            builder.timeout(timeout).send().await?
+        };
+        let metadata = ResponseMetadata::from_headers(response.status(), response.headers());
+        if let Some(limiter) = &self.limiter {
+            limiter.observe(&metadata);
+        }
+        Ok((response, metadata))
+    }
+
+    /// Sends the completion request and decodes the completion response
+    pub(crate) async fn complete(
+        &self,
+        request: &CompletionRequest<'_>,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse> {
+        #[cfg(feature = "json")]
+        let cache_key = match &self.cache {
+            Some(cache) => self.cache_key(cache, request)?,
+            None => None,
+        };
+        #[cfg(feature = "json")]
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            if let Some(response) = cache.get(key).await? {
+                return Ok(crate::cache::mark_cached(response));
+            }
+        }
+        let attempt = |engine: ChatGPTEngine| async move {
+            let request = CompletionRequest {
+                model: engine.as_ref(),
+                ..request.clone()
+            };
+            let (response, mut metadata) = self.send_completion_request(&request, options).await?;
+            metadata.engine = Some(engine.clone());
+            let completion: CompletionResponse = decode_with_metadata(response, &metadata).await?;
+            Ok(CompletionResponse {
+                metadata: Some(metadata),
+                ..completion
             })
-            .send()
+        };
+        let response = options
+            .run_until(options.deadline_instant(), self.with_fallback(attempt))
             .await?;
+        #[cfg(feature = "json")]
+        if let (Some(cache), Some(_)) = (&self.cache, &cache_key) {
+            // Stored for the engine that answered, which differs from the requested one if a fallback engine was used
+            let engine = response
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.engine.as_ref());
+            let answered = CompletionRequest {
+                model: engine.map_or(request.model, ChatGPTEngine::as_ref),
+                ..request.clone()
+            };
+            // Caching is best effort, the completion was received and billed regardless
+            if let Ok(Some(key)) = self.cache_key(cache, &answered) {
+                let _ = cache.put(&key, &response).await;
+            }
+        }
+        Ok(response)
+    }
 
-        Self::process_streaming_response(response)
+    /// Computes the cache key of the request, which includes the endpoint and the credentials of this client
+    #[cfg(feature = "json")]
+    pub(crate) fn cache_key(
+        &self,
+        cache: &ResponseCache,
+        request: &CompletionRequest<'_>,
+    ) -> crate::Result<Option<CacheKey>> {
+        let mut credentials = Vec::new();
+        for name in [AUTHORIZATION.as_str(), "openai-organization"] {
+            if let Some(value) = self.headers.get(name) {
+                credentials.extend_from_slice(value.as_bytes());
+            }
+            credentials.push(b'\n');
+        }
+        cache.key(&self.config.api_url, &credentials, request)
     }
 
+    /// Attempts the request with the configured engine, and then with the fallback engines
+    /// for as long as the request fails with one of the fallback triggers.
+    async fn with_fallback<T, F, Fut>(&self, mut attempt: F) -> crate::Result<T>
+    where
+        F: FnMut(ChatGPTEngine) -> Fut,
+        Fut: Future<Output = crate::Result<T>>,
+    {
+        let mut fallbacks = self.config.fallback_engines.iter();
+        let mut engine = &self.config.engine;
+        loop {
+            let err = match attempt(engine.clone()).await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let Some(trigger) = self
+                .config
+                .fallback_triggers
+                .iter()
+                .find(|trigger| trigger.matches(&err))
+            else {
+                return Err(err);
+            };
+            match fallbacks.find(|fallback| trigger.accepts(engine, fallback)) {
+                Some(fallback) => engine = fallback,
+                None => return Err(err),
+            }
+        }
+    }
+
+    /// Parses the chunks of a successful streamed response, the status is checked by the caller
     #[cfg(feature = "streams")]
     fn process_streaming_response(
         response: Response,
-    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
+        metadata: ResponseMetadata,
+    ) -> impl Stream<Item = ResponseChunk> {
         use eventsource_stream::Eventsource;
         use futures_util::StreamExt;
 
-        // also handles errors
-        response
-            .error_for_status()
-            .map(|response| {
-                let response_stream = response.bytes_stream().eventsource();
-                response_stream.map(move |part| {
-                    let chunk = &part.expect("Stream closed abruptly!").data;
-                    if chunk == "[DONE]" {
-                        return ResponseChunk::Done;
-                    }
-                    let data: InboundResponseChunk = serde_json::from_str(chunk)
-                        .expect("Invalid inbound streaming response payload!");
-                    let choice = data.choices[0].to_owned();
-                    match choice.delta {
-                        InboundChunkPayload::AnnounceRoles { role } => {
-                            ResponseChunk::BeginResponse {
-                                role,
-                                response_index: choice.index,
-                            }
-                        }
-                        InboundChunkPayload::StreamContent { content } => ResponseChunk::Content {
-                            delta: content,
-                            response_index: choice.index,
-                        },
-                        InboundChunkPayload::Close {} => ResponseChunk::CloseResponse {
-                            response_index: choice.index,
-                        },
-                    }
+        let response_stream = response.bytes_stream().eventsource();
+        let metadata = futures_util::stream::once(futures_util::future::ready(
+            ResponseChunk::Metadata(metadata),
+        ));
+        let chunks = response_stream
+            .filter_map(|part| {
+                futures_util::future::ready(match part {
+                    Ok(event) => parse_stream_chunk(&event.data),
+                    Err(_) => Some(ResponseChunk::Aborted {
+                        reason: AbortReason::ConnectionLost,
+                    }),
                 })
             })
-            .map_err(crate::err::Error::from)
+            // No more chunks are sent after the stream was aborted
+            .scan(false, |aborted, chunk| {
+                let end =
+                    std::mem::replace(aborted, matches!(chunk, ResponseChunk::Aborted { .. }));
+                futures_util::future::ready((!end).then_some(chunk))
+            });
+        metadata.chain(chunks)
     }
 
     /// Sends a message with specified function descriptors. ChatGPT is then able to call these functions.
@@ -356,39 +599,11 @@ impl ChatGPT {
         message: S,
         baked_functions: Vec<serde_json::Value>,
     ) -> crate::Result<CompletionResponse> {
-        let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
-                model: self.config.engine.as_ref(),
-                messages: &vec![ChatMessage {
-                    role: Role::User,
-                    content: message.into(),
-                    #[cfg(feature = "functions")]
-                    function_call: None,
-                }],
-                stream: false,
-                temperature: self.config.temperature,
-                top_p: self.config.top_p,
-                frequency_penalty: self.config.frequency_penalty,
-                presence_penalty: self.config.presence_penalty,
-                reply_count: self.config.reply_count,
-                max_tokens: self.config.max_tokens,
-                #[cfg(feature = "functions")]
-                functions: &baked_functions,
-            })
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        match response {
-            ServerResponse::Error { error } => Err(crate::err::Error::BackendError {
-                message: error.message,
-                error_type: error.error_type,
-            }),
-            ServerResponse::Completion(completion) => Ok(completion),
-        }
+        self.send_history_functions(
+            &vec![ChatMessage::new(Role::User, message)],
+            &baked_functions,
+        )
+        .await
     }
 
     /// Sends whole message history alongside with defined baked functions.
@@ -398,31 +613,665 @@ impl ChatGPT {
         history: &Vec<ChatMessage>,
         functions: &Vec<serde_json::Value>,
     ) -> crate::Result<CompletionResponse> {
-        let response: ServerResponse = self
-            .client
-            .post(self.config.api_url.clone())
-            .json(&CompletionRequest {
-                model: self.config.engine.as_ref(),
-                messages: history,
-                stream: false,
-                temperature: self.config.temperature,
-                top_p: self.config.top_p,
-                frequency_penalty: self.config.frequency_penalty,
-                presence_penalty: self.config.presence_penalty,
-                reply_count: self.config.reply_count,
-                max_tokens: self.config.max_tokens,
-                functions,
-            })
-            .send()
-            .await?
-            .json()
-            .await?;
-        match response {
-            ServerResponse::Error { error } => Err(crate::err::Error::BackendError {
-                message: error.message,
-                error_type: error.error_type,
-            }),
-            ServerResponse::Completion(completion) => Ok(completion),
+        let mut request = self.completion_request(history, false);
+        request.functions = functions;
+        self.complete(&request, &RequestOptions::default()).await
+    }
+}
+
+/// Parses the data of a single streamed event, skipping events without choices
+#[cfg(feature = "streams")]
+fn parse_stream_chunk(data: &str) -> Option<ResponseChunk> {
+    if data == "[DONE]" {
+        return Some(ResponseChunk::Done);
+    }
+    let Ok(data) = serde_json::from_str::<InboundResponseChunk>(data) else {
+        return Some(ResponseChunk::Aborted {
+            reason: AbortReason::InvalidChunk,
+        });
+    };
+    let choice = data.choices.into_iter().next()?;
+    Some(match choice.delta {
+        InboundChunkPayload::AnnounceRoles { role } => ResponseChunk::BeginResponse {
+            role,
+            response_index: choice.index,
+        },
+        InboundChunkPayload::StreamContent { content } => ResponseChunk::Content {
+            delta: content,
+            response_index: choice.index,
+        },
+        InboundChunkPayload::Close {} => ResponseChunk::CloseResponse {
+            response_index: choice.index,
+        },
+    })
+}
+
+/// Retries the attempt while it fails with a [retryable](Error::is_retryable) error, up to `max_retries` times
+pub(crate) async fn with_retries<T, F, Fut>(
+    max_retries: u32,
+    delay: Duration,
+    mut attempt: F,
+) -> crate::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = crate::Result<T>>,
+{
+    let mut retries = 0;
+    loop {
+        match attempt().await {
+            Err(err) if err.is_retryable() && retries < max_retries => {
+                tokio::time::sleep(retry_delay(&err, retries, delay)).await;
+                retries += 1;
+            }
+            result => return result,
         }
     }
 }
+
+/// The delay before the next retry, which doubles with each retry starting at `delay`.
+///
+/// The delay requested by the API is used instead when rate limited.
+pub(crate) fn retry_delay(error: &Error, retries: u32, delay: Duration) -> Duration {
+    match error {
+        Error::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        } => *retry_after,
+        _ => delay * 2u32.saturating_pow(retries),
+    }
+}
+
+/// A type-erased stream of response chunks, either received from the API or replayed from the cache
+#[cfg(feature = "streams")]
+type BoxedChunkStream = std::pin::Pin<Box<dyn Stream<Item = ResponseChunk> + Send>>;
+
+/// Applies cancellation, the deadline and the idle timeout to a streamed response.
+///
+/// Dropping the inner stream when the stream is aborted also aborts the underlying HTTP request.
+#[cfg(feature = "streams")]
+fn guard_stream(
+    stream: impl Stream<Item = ResponseChunk> + Send + 'static,
+    cancellation: Option<CancellationToken>,
+    deadline: Option<Instant>,
+    idle_timeout: Duration,
+) -> impl Stream<Item = ResponseChunk> {
+    use futures_util::StreamExt;
+
+    let inner = Box::pin(stream);
+    Box::pin(futures_util::stream::unfold(Some(inner), move |inner| {
+        let cancellation = cancellation.clone();
+        async move {
+            let mut inner = inner?;
+            let cancelled = async {
+                match &cancellation {
+                    Some(token) => token.cancelled().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let expired = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let next = tokio::select! {
+                biased;
+                _ = cancelled => Err(AbortReason::Cancelled),
+                _ = expired => Err(AbortReason::DeadlineExceeded),
+                next = tokio::time::timeout(idle_timeout, inner.next()) => {
+                    next.map_err(|_| AbortReason::IdleTimeout)
+                }
+            };
+            match next {
+                Ok(Some(chunk)) => Some((chunk, Some(inner))),
+                Ok(None) => None,
+                Err(reason) => Some((ResponseChunk::Aborted { reason }, None)),
+            }
+        }
+    }))
+}
+
+/// A generic response from the API, which might contain an error instead of the expected value
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ApiResponse<T> {
+    Error { error: CompletionError },
+    Success(T),
+}
+
+/// Decodes the API response body, turning API errors into typed [`Error`]s
+pub(crate) async fn decode_response<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> crate::Result<T> {
+    let metadata = ResponseMetadata::from_headers(response.status(), response.headers());
+    decode_with_metadata(response, &metadata).await
+}
+
+/// Returns the response if it was successful, and the typed API error otherwise
+pub(crate) async fn error_for_status(response: reqwest::Response) -> crate::Result<Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let metadata = ResponseMetadata::from_headers(response.status(), response.headers());
+    Err(api_error(response, &metadata).await)
+}
+
+/// Decodes the API response body, using the already extracted response metadata to classify errors
+async fn decode_with_metadata<T: DeserializeOwned>(
+    response: reqwest::Response,
+    metadata: &ResponseMetadata,
+) -> crate::Result<T> {
+    if !response.status().is_success() {
+        return Err(api_error(response, metadata).await);
+    }
+    match response.json::<ApiResponse<T>>().await? {
+        ApiResponse::Error { error } => Err(classify_error(error, metadata)),
+        ApiResponse::Success(value) => Ok(value),
+    }
+}
+
+/// Reads the error from an unsuccessful response. Bodies without an API error are reported with the HTTP status reason.
+async fn api_error(response: reqwest::Response, metadata: &ResponseMetadata) -> Error {
+    let status = response.status();
+    match response.json::<ApiResponse<serde::de::IgnoredAny>>().await {
+        Ok(ApiResponse::Error { error }) => classify_error(error, metadata),
+        _ => Error::from_api(
+            ApiError {
+                message: status
+                    .canonical_reason()
+                    .unwrap_or("Unknown error")
+                    .to_string(),
+                status: status.as_u16(),
+                request_id: metadata.request_id.clone(),
+                ..Default::default()
+            },
+            metadata.retry_after,
+        ),
+    }
+}
+
+fn classify_error(error: CompletionError, metadata: &ResponseMetadata) -> Error {
+    Error::from_api(
+        ApiError {
+            message: error.message,
+            error_type: error.error_type,
+            code: error.code,
+            status: metadata.status,
+            request_id: metadata.request_id.clone(),
+        },
+        metadata.retry_after,
+    )
+}
+
+/// Source from which the API key is obtained when building the client
+#[derive(Debug, Clone)]
+enum ApiKeySource {
+    Value(String),
+    Env(String),
+    File(PathBuf),
+}
+
+impl ApiKeySource {
+    fn resolve(self) -> crate::Result<String> {
+        let key = match self {
+            ApiKeySource::Value(key) => key,
+            ApiKeySource::Env(var) => std::env::var(var)?,
+            ApiKeySource::File(path) => std::fs::read_to_string(path)?,
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(Error::BuilderError(
+                "the provided API key is empty".to_string(),
+            ));
+        }
+        Ok(key.to_string())
+    }
+}
+
+/// Builder for the [`ChatGPT`] client. Can be obtained with [`ChatGPT::builder()`].
+///
+/// Unless a custom client is provided via [`ChatGPTBuilder::client()`], a new `reqwest` client is constructed
+/// with the proxy, TLS and timeout options set on this builder.
+#[derive(Debug, Default)]
+pub struct ChatGPTBuilder {
+    api_key: Option<ApiKeySource>,
+    organization: Option<String>,
+    config: Option<ModelConfiguration>,
+    base_url: Option<String>,
+    client: Option<reqwest::Client>,
+    proxies: Vec<Proxy>,
+    root_certificates: Vec<Certificate>,
+    built_in_root_certificates: Option<bool>,
+    accept_invalid_certificates: bool,
+    min_tls_version: Option<Version>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    #[cfg(feature = "json")]
+    cache: Option<ResponseCache>,
+}
+
+/// Environment variable containing the API key
+pub const API_KEY_ENV: &str = "OPENAI_API_KEY";
+/// Environment variable containing the API base URL
+pub const BASE_URL_ENV: &str = "OPENAI_BASE_URL";
+/// Environment variable containing the organization ID
+pub const ORG_ID_ENV: &str = "OPENAI_ORG_ID";
+/// Environment variable overriding the model ID
+pub const MODEL_ENV: &str = "OPENAI_MODEL";
+/// Environment variable overriding the sampling temperature
+pub const TEMPERATURE_ENV: &str = "OPENAI_TEMPERATURE";
+/// Environment variable overriding the maximum amount of generated tokens
+pub const MAX_TOKENS_ENV: &str = "OPENAI_MAX_TOKENS";
+
+impl ChatGPTBuilder {
+    /// Creates a builder configured from the environment variables:
+    /// - `OPENAI_API_KEY` (required) - the API key
+    /// - `OPENAI_BASE_URL` - the API base URL
+    /// - `OPENAI_ORG_ID` - the organization ID
+    /// - `OPENAI_MODEL` - the model ID, overriding [`ModelConfiguration::engine`]
+    /// - `OPENAI_TEMPERATURE` - overrides [`ModelConfiguration::temperature`]
+    /// - `OPENAI_MAX_TOKENS` - overrides [`ModelConfiguration::max_tokens`]
+    ///
+    /// Note that setting a configuration with [`Self::config()`] afterwards will discard the model overrides.
+    pub fn from_env() -> crate::Result<Self> {
+        let mut builder = Self::default().api_key(std::env::var(API_KEY_ENV)?);
+        if let Some(base_url) = optional_env(BASE_URL_ENV)? {
+            builder = builder.base_url(base_url);
+        }
+        if let Some(organization) = optional_env(ORG_ID_ENV)? {
+            builder = builder.organization(organization);
+        }
+
+        let mut config = ModelConfiguration::default();
+        if let Some(model) = optional_env(MODEL_ENV)? {
+            // Parsing is infallible
+            config.engine = model.parse::<ChatGPTEngine>().unwrap();
+        }
+        if let Some(temperature) = optional_env(TEMPERATURE_ENV)? {
+            config.temperature = temperature.parse().map_err(|_| {
+                Error::ParsingError(format!("Invalid {TEMPERATURE_ENV} value: {temperature}"))
+            })?;
+        }
+        if let Some(max_tokens) = optional_env(MAX_TOKENS_ENV)? {
+            config.max_tokens = Some(max_tokens.parse().map_err(|_| {
+                Error::ParsingError(format!("Invalid {MAX_TOKENS_ENV} value: {max_tokens}"))
+            })?);
+        }
+        Ok(builder.config(config))
+    }
+
+    /// Sets the API key used to authenticate requests.
+    ///
+    /// If no API key source is set, requests are sent without the `Authorization` header, which
+    /// can be useful for some proxies.
+    pub fn api_key<S: Into<String>>(mut self, api_key: S) -> Self {
+        self.api_key = Some(ApiKeySource::Value(api_key.into()));
+        self
+    }
+
+    /// Reads the API key from the provided environment variable when building the client
+    pub fn api_key_env<S: Into<String>>(mut self, variable: S) -> Self {
+        self.api_key = Some(ApiKeySource::Env(variable.into()));
+        self
+    }
+
+    /// Reads the API key from the provided file when building the client. Surrounding whitespace is trimmed.
+    pub fn api_key_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.api_key = Some(ApiKeySource::File(path.into()));
+        self
+    }
+
+    /// Sets the organization ID, sent with each request in the `OpenAI-Organization` header
+    pub fn organization<S: Into<String>>(mut self, organization: S) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Sets the model configuration. Defaults to [`ModelConfiguration::default()`]
+    pub fn config(mut self, config: ModelConfiguration) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets the base URL of the API, e.g. `https://api.openai.com/v1/`.
+    ///
+    /// The completions endpoint is resolved relative to it, overriding the `api_url` of the model configuration.
+    pub fn base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Uses a preconfigured `reqwest` client instead of constructing a new one.
+    ///
+    /// Cannot be combined with the proxy, TLS and connect timeout options of this builder,
+    /// as those have to be configured on the provided client itself.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Adds a proxy to the constructed client
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Adds a custom root certificate to trust
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Controls whether the built-in root certificates are trusted. Enabled by default.
+    pub fn built_in_root_certificates(mut self, enabled: bool) -> Self {
+        self.built_in_root_certificates = Some(enabled);
+        self
+    }
+
+    /// Disables TLS certificate validation.
+    ///
+    /// **Warning**: this makes the connection vulnerable to man-in-the-middle attacks,
+    /// and should only be used for local development.
+    pub fn danger_accept_invalid_certificates(mut self, accept: bool) -> Self {
+        self.accept_invalid_certificates = accept;
+        self
+    }
+
+    /// Sets the minimum accepted TLS version
+    pub fn min_tls_version(mut self, version: Version) -> Self {
+        self.min_tls_version = Some(version);
+        self
+    }
+
+    /// Sets the timeout for establishing a connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the total timeout of each request, overriding the `timeout` of the model configuration
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Enables client-side rate limiting. Clones of the built client share the same limits.
+    ///
+    /// Requests exceeding the limits wait for the budget to be replenished, in the order they were issued.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Enables caching of completion responses. Clones of the built client share the same cache.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub fn cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Validates the options and builds the client
+    pub fn build(self) -> crate::Result<ChatGPT> {
+        let mut config = self.config.unwrap_or_default();
+        if let Some(timeout) = self.timeout {
+            config.timeout = timeout;
+        }
+        if config.timeout.is_zero() || self.connect_timeout.is_some_and(|t| t.is_zero()) {
+            return Err(Error::BuilderError(
+                "timeouts must be greater than zero".to_string(),
+            ));
+        }
+        if self.rate_limit.is_some_and(|limit| {
+            limit.requests_per_minute == Some(0) || limit.tokens_per_minute == Some(0)
+        }) {
+            return Err(Error::BuilderError(
+                "rate limits must be greater than zero".to_string(),
+            ));
+        }
+        if let Some(base_url) = self.base_url {
+            config.api_url = completions_url(&base_url)?;
+        }
+
+        let mut headers = HeaderMap::new();
+        if let Some(source) = self.api_key {
+            let api_key = source.resolve()?;
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_bytes(format!("Bearer {api_key}").as_bytes())?,
+            );
+        }
+        if let Some(organization) = self.organization {
+            headers.insert(
+                HeaderName::from_static("openai-organization"),
+                HeaderValue::from_str(&organization)?,
+            );
+        }
+
+        let customizes_client = !self.proxies.is_empty()
+            || !self.root_certificates.is_empty()
+            || self.built_in_root_certificates.is_some()
+            || self.accept_invalid_certificates
+            || self.min_tls_version.is_some()
+            || self.connect_timeout.is_some();
+        let client = match self.client {
+            Some(_) if customizes_client => return Err(Error::BuilderError(
+                "proxy, TLS and connect timeout options can not be combined with a custom client"
+                    .to_string(),
+            )),
+            Some(client) => client,
+            None => {
+                let mut builder = reqwest::ClientBuilder::new()
+                    .danger_accept_invalid_certs(self.accept_invalid_certificates);
+                for proxy in self.proxies {
+                    builder = builder.proxy(proxy);
+                }
+                for certificate in self.root_certificates {
+                    builder = builder.add_root_certificate(certificate);
+                }
+                if let Some(enabled) = self.built_in_root_certificates {
+                    builder = builder.tls_built_in_root_certs(enabled);
+                }
+                if let Some(version) = self.min_tls_version {
+                    builder = builder.min_tls_version(version);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                builder.build()?
+            }
+        };
+
+        Ok(ChatGPT {
+            client,
+            headers,
+            limiter: self
+                .rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+            pool: None,
+            #[cfg(feature = "json")]
+            cache: self.cache.map(Arc::new),
+            config,
+        })
+    }
+}
+
+/// Reads an environment variable, treating missing and empty variables as absent
+fn optional_env(variable: &str) -> crate::Result<Option<String>> {
+    match std::env::var(variable) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Resolves the completions endpoint from the API base URL
+fn completions_url(base_url: &str) -> crate::Result<Url> {
+    let mut base = Url::parse(base_url)
+        .map_err(|err| Error::BuilderError(format!("invalid base URL `{base_url}`: {err}")))?;
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join("chat/completions")
+        .map_err(|err| Error::BuilderError(format!("invalid base URL `{base_url}`: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{client::ChatGPT, err::Error};
+
+    #[test]
+    fn test_builder_base_url() {
+        let client = ChatGPT::builder()
+            .api_key("key")
+            .base_url("http://localhost:8080/v1")
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.config.api_url.as_str(),
+            "http://localhost:8080/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_resolution() {
+        let client = ChatGPT::builder()
+            .base_url("http://localhost:8080/v1/")
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.endpoint("models/gpt-4").unwrap().as_str(),
+            "http://localhost:8080/v1/models/gpt-4"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        use crate::client::retry_delay;
+        use crate::err::{ApiError, Error};
+
+        let delay = Duration::from_millis(100);
+        assert_eq!(
+            retry_delay(&Error::Timeout, 2, delay),
+            Duration::from_millis(400)
+        );
+        let rate_limited = Error::RateLimited {
+            error: ApiError::default(),
+            retry_after: Some(Duration::from_secs(3)),
+        };
+        assert_eq!(retry_delay(&rate_limited, 0, delay), Duration::from_secs(3));
+    }
+
+    #[cfg(feature = "streams")]
+    #[tokio::test]
+    async fn test_stream_guard() {
+        use futures::StreamExt;
+
+        use crate::client::guard_stream;
+        use crate::config::CancellationToken;
+        use crate::types::{AbortReason, ResponseChunk};
+
+        let idle = guard_stream(
+            futures::stream::iter([ResponseChunk::Done]).chain(futures::stream::pending()),
+            None,
+            None,
+            Duration::from_millis(10),
+        );
+        assert_eq!(
+            idle.collect::<Vec<_>>().await,
+            vec![
+                ResponseChunk::Done,
+                ResponseChunk::Aborted {
+                    reason: AbortReason::IdleTimeout
+                }
+            ]
+        );
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancelled = guard_stream(
+            futures::stream::pending(),
+            Some(token),
+            None,
+            Duration::from_secs(10),
+        );
+        assert_eq!(
+            cancelled.collect::<Vec<_>>().await,
+            vec![ResponseChunk::Aborted {
+                reason: AbortReason::Cancelled
+            }]
+        );
+    }
+
+    #[cfg(feature = "streams")]
+    #[test]
+    fn test_parse_stream_chunk() {
+        use crate::client::parse_stream_chunk;
+        use crate::types::{AbortReason, ResponseChunk};
+
+        assert_eq!(parse_stream_chunk("[DONE]"), Some(ResponseChunk::Done));
+        assert_eq!(
+            parse_stream_chunk(r#"{"choices": [{"index": 1, "delta": {"content": "Hi"}}]}"#),
+            Some(ResponseChunk::Content {
+                delta: "Hi".to_string(),
+                response_index: 1
+            })
+        );
+        // Usage chunks carry no choices
+        assert_eq!(parse_stream_chunk(r#"{"choices": []}"#), None);
+        assert_eq!(
+            parse_stream_chunk("{"),
+            Some(ResponseChunk::Aborted {
+                reason: AbortReason::InvalidChunk
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_options() {
+        use crate::config::{CancellationToken, RequestOptionsBuilder};
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let options = RequestOptionsBuilder::default()
+            .cancellation(token)
+            .build()
+            .unwrap();
+        let cancelled = options
+            .run_until(None, std::future::pending::<crate::Result<()>>())
+            .await;
+        assert!(matches!(cancelled, Err(Error::Cancelled)));
+
+        let options = RequestOptionsBuilder::default()
+            .deadline(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let expired = options
+            .run_until(
+                options.deadline_instant(),
+                std::future::pending::<crate::Result<()>>(),
+            )
+            .await;
+        assert!(matches!(expired, Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let custom_with_proxy = ChatGPT::builder()
+            .client(reqwest::Client::new())
+            .proxy(reqwest::Proxy::all("http://localhost:3128").unwrap())
+            .build();
+        assert!(matches!(custom_with_proxy, Err(Error::BuilderError(_))));
+
+        let zero_timeout = ChatGPT::builder().timeout(Duration::ZERO).build();
+        assert!(matches!(zero_timeout, Err(Error::BuilderError(_))));
+
+        let empty_key = ChatGPT::builder().api_key("  ").build();
+        assert!(matches!(empty_key, Err(Error::BuilderError(_))));
+    }
+}
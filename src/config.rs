@@ -1,17 +1,32 @@
-use std::{fmt::Display, str::FromStr};
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::future::Future;
+#[cfg(any(feature = "json", feature = "toml"))]
+use std::path::Path;
 use std::time::Duration;
+use std::{fmt::Display, str::FromStr};
 
 #[cfg(feature = "functions")]
 use crate::functions::FunctionValidationStrategy;
 use derive_builder::Builder;
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::time::Instant;
+pub use tokio_util::sync::CancellationToken;
 
 /// The struct containing main configuration for the ChatGPT API
-#[derive(Debug, Clone, PartialEq, PartialOrd, Builder)]
+///
+/// The configuration can be (de)serialized, e.g. from a configuration file. Missing fields are set to their default values,
+/// and the `timeout` is represented in seconds.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Builder, Serialize, Deserialize)]
 #[builder(default, setter(into))]
+#[serde(default)]
 pub struct ModelConfiguration {
     /// The GPT version used.
     pub engine: ChatGPTEngine,
+    /// Engines that are tried in order whenever a request fails with one of the `fallback_triggers`
+    pub fallback_engines: Vec<ChatGPTEngine>,
+    /// Errors that cause the request to be retried with the next of the `fallback_engines`
+    pub fallback_triggers: Vec<FallbackTrigger>,
     /// Controls randomness of the output. Higher values means more random
     pub temperature: f32,
     /// Controls diversity via nucleus sampling, not recommended to use with temperature
@@ -27,7 +42,13 @@ pub struct ModelConfiguration {
     /// URL of the /v1/chat/completions endpoint. Can be used to set a proxy
     pub api_url: url::Url,
     /// Timeout for the http requests sent to avoid potentially permanently hanging requests.
+    ///
+    /// For streamed responses, this only limits the time until the response starts, see `stream_idle_timeout`.
+    #[serde(with = "duration_secs")]
     pub timeout: Duration,
+    /// Maximum time to wait between two chunks of a streamed response
+    #[serde(with = "duration_secs")]
+    pub stream_idle_timeout: Duration,
     /// Strategy for function validation strategy. Whenever ChatGPT fails to call a function correctly, this strategy is applied.
     #[cfg(feature = "functions")]
     pub function_validation: FunctionValidationStrategy,
@@ -37,6 +58,8 @@ impl Default for ModelConfiguration {
     fn default() -> Self {
         Self {
             engine: Default::default(),
+            fallback_engines: Vec::new(),
+            fallback_triggers: FallbackTrigger::ALL.to_vec(),
             temperature: 0.5,
             top_p: 1.0,
             max_tokens: None,
@@ -45,14 +68,161 @@ impl Default for ModelConfiguration {
             reply_count: 1,
             api_url: url::Url::from_str("https://api.openai.com/v1/chat/completions").unwrap(),
             timeout: Duration::from_secs(10),
+            stream_idle_timeout: Duration::from_secs(60),
             #[cfg(feature = "functions")]
             function_validation: FunctionValidationStrategy::default(),
         }
     }
 }
 
+impl ModelConfiguration {
+    /// Reads the configuration from a JSON or TOML file, depending on the file extension.
+    ///
+    /// JSON files require the `json` feature, and TOML files require the `toml` feature.
+    #[cfg(any(feature = "json", feature = "toml"))]
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        match extension {
+            #[cfg(feature = "json")]
+            Some("json") => {
+                let content = tokio::fs::read(path).await?;
+                Ok(serde_json::from_slice(&content)?)
+            }
+            #[cfg(feature = "toml")]
+            Some("toml") => {
+                let content = tokio::fs::read_to_string(path).await?;
+                Ok(toml::from_str(&content)?)
+            }
+            _ => Err(crate::err::Error::ParsingError(format!(
+                "Unsupported configuration file format: {}",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// An error that causes a request to be retried with a fallback engine, see [`ModelConfiguration::fallback_engines`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackTrigger {
+    /// The model is overloaded, or the server failed to process the request
+    ServerError,
+    /// The prompt does not fit into the context window of the model.
+    /// Only fallback engines with a larger known context window are tried.
+    ContextLengthExceeded,
+    /// The rate limit or quota of the model was exceeded
+    RateLimited,
+    /// The model does not exist, or the API key has no access to it
+    ModelUnavailable,
+}
+
+impl FallbackTrigger {
+    /// All fallback triggers
+    pub const ALL: [FallbackTrigger; 4] = [
+        FallbackTrigger::ServerError,
+        FallbackTrigger::ContextLengthExceeded,
+        FallbackTrigger::RateLimited,
+        FallbackTrigger::ModelUnavailable,
+    ];
+
+    /// Checks whether the error is covered by this trigger
+    pub fn matches(&self, error: &crate::err::Error) -> bool {
+        use crate::err::Error;
+
+        matches!(
+            (self, error),
+            (FallbackTrigger::ServerError, Error::ServerError { .. })
+                | (
+                    FallbackTrigger::ContextLengthExceeded,
+                    Error::ContextLengthExceeded(_)
+                )
+                | (FallbackTrigger::RateLimited, Error::RateLimited { .. })
+                | (FallbackTrigger::ModelUnavailable, Error::NotFound(_))
+        )
+    }
+
+    /// Checks whether the fallback engine may be tried after the failed engine
+    pub fn accepts(&self, failed: &ChatGPTEngine, fallback: &ChatGPTEngine) -> bool {
+        match self {
+            FallbackTrigger::ContextLengthExceeded => {
+                match (failed.context_window(), fallback.context_window()) {
+                    (Some(failed), Some(fallback)) => fallback > failed,
+                    _ => true,
+                }
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Options that apply to a single request, e.g. [`ChatGPT::send_history_with_options()`](crate::client::ChatGPT::send_history_with_options)
+#[derive(Debug, Clone, Default, Builder)]
+#[builder(default, setter(into, strip_option))]
+pub struct RequestOptions {
+    /// Total time the call may take, including waiting for the rate limiter and receiving the whole streamed response.
+    ///
+    /// Overrides the `timeout` of the model configuration.
+    pub deadline: Option<Duration>,
+    /// Maximum time to wait between two chunks of a streamed response, overriding the `stream_idle_timeout` of the model configuration
+    pub stream_idle_timeout: Option<Duration>,
+    /// Token that aborts the in-flight request when cancelled
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl RequestOptions {
+    /// Runs the future until it completes, the deadline expires or the request is cancelled
+    pub(crate) async fn run_until<T>(
+        &self,
+        deadline: Option<Instant>,
+        future: impl Future<Output = crate::Result<T>>,
+    ) -> crate::Result<T> {
+        let cancelled = async {
+            match &self.cancellation {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        let expired = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = cancelled => Err(crate::err::Error::Cancelled),
+            _ = expired => Err(crate::err::Error::Timeout),
+            result = future => result,
+        }
+    }
+
+    /// Resolves the deadline of a call starting now
+    pub(crate) fn deadline_instant(&self) -> Option<Instant> {
+        self.deadline.map(|deadline| Instant::now() + deadline)
+    }
+}
+
+/// (De)serializes durations as a (possibly fractional) amount of seconds
+mod duration_secs {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(secs).map_err(D::Error::custom)
+    }
+}
+
 /// The engine version for ChatGPT
-#[derive(Serialize, Debug, Default, Copy, Clone, PartialEq, PartialOrd)]
+///
+/// Engines are (de)serialized as their model IDs, e.g. `gpt-4`. Unknown model IDs are deserialized as [`ChatGPTEngine::Custom`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[allow(non_camel_case_types)]
 pub enum ChatGPTEngine {
     /// Standard engine: `gpt-3.5-turbo`
@@ -60,6 +230,14 @@ pub enum ChatGPTEngine {
     Gpt35Turbo,
     /// Different version of standard engine: `gpt-3.5-turbo-0301`
     Gpt35Turbo_0301,
+    /// Different version of standard engine, supporting functions: `gpt-3.5-turbo-0613`
+    Gpt35Turbo_0613,
+    /// Different version of standard engine, supporting JSON mode: `gpt-3.5-turbo-1106`
+    Gpt35Turbo_1106,
+    /// Different version of standard engine, supporting JSON mode: `gpt-3.5-turbo-0125`
+    Gpt35Turbo_0125,
+    /// Version of standard engine, able to remember 16,000 tokens: `gpt-3.5-turbo-16k`
+    Gpt35Turbo_16k,
     /// Base GPT-4 model: `gpt-4`
     Gpt4,
     /// Version of GPT-4, able to remember 32,000 tokens: `gpt-4-32k`
@@ -68,8 +246,129 @@ pub enum ChatGPTEngine {
     Gpt4_0314,
     /// Different version of GPT-4, able to remember 32,000 tokens: `gpt-4-32k-0314`
     Gpt4_32k_0314,
+    /// Different version of GPT-4, supporting functions: `gpt-4-0613`
+    Gpt4_0613,
+    /// Different version of GPT-4, supporting functions and able to remember 32,000 tokens: `gpt-4-32k-0613`
+    Gpt4_32k_0613,
+    /// GPT-4 Turbo, able to remember 128,000 tokens and process images: `gpt-4-turbo`
+    Gpt4Turbo,
+    /// Preview version of GPT-4 Turbo: `gpt-4-turbo-preview`
+    Gpt4Turbo_Preview,
+    /// Multimodal GPT-4o model: `gpt-4o`
+    Gpt4o,
+    /// Smaller and cheaper version of GPT-4o: `gpt-4o-mini`
+    Gpt4oMini,
     /// Custom (or new/unimplemented) version of ChatGPT
-    Custom(&'static str),
+    Custom(Cow<'static, str>),
+}
+
+/// Known capabilities and limits of a [`ChatGPTEngine`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EngineInfo {
+    /// Maximum amount of tokens in the prompt and the completion combined
+    pub context_window: u32,
+    /// Maximum amount of tokens in the completion, if it is limited separately from the context window
+    pub max_output_tokens: Option<u32>,
+    /// Whether the engine supports function calling
+    pub supports_functions: bool,
+    /// Whether the engine accepts images as input
+    pub supports_vision: bool,
+    /// Whether the engine supports the JSON response format
+    pub supports_json_mode: bool,
+}
+
+impl EngineInfo {
+    const fn new(
+        context_window: u32,
+        max_output_tokens: Option<u32>,
+        supports_functions: bool,
+        supports_vision: bool,
+        supports_json_mode: bool,
+    ) -> Self {
+        Self {
+            context_window,
+            max_output_tokens,
+            supports_functions,
+            supports_vision,
+            supports_json_mode,
+        }
+    }
+}
+
+impl ChatGPTEngine {
+    /// All engines known to this library, excluding [`ChatGPTEngine::Custom`]
+    pub const KNOWN: &'static [ChatGPTEngine] = &[
+        ChatGPTEngine::Gpt35Turbo,
+        ChatGPTEngine::Gpt35Turbo_0301,
+        ChatGPTEngine::Gpt35Turbo_0613,
+        ChatGPTEngine::Gpt35Turbo_1106,
+        ChatGPTEngine::Gpt35Turbo_0125,
+        ChatGPTEngine::Gpt35Turbo_16k,
+        ChatGPTEngine::Gpt4,
+        ChatGPTEngine::Gpt4_32k,
+        ChatGPTEngine::Gpt4_0314,
+        ChatGPTEngine::Gpt4_32k_0314,
+        ChatGPTEngine::Gpt4_0613,
+        ChatGPTEngine::Gpt4_32k_0613,
+        ChatGPTEngine::Gpt4Turbo,
+        ChatGPTEngine::Gpt4Turbo_Preview,
+        ChatGPTEngine::Gpt4o,
+        ChatGPTEngine::Gpt4oMini,
+    ];
+
+    /// Returns known capabilities and limits of this engine, or `None` for [`ChatGPTEngine::Custom`] engines
+    pub fn info(&self) -> Option<EngineInfo> {
+        Some(match self {
+            ChatGPTEngine::Gpt35Turbo => EngineInfo::new(16_385, Some(4_096), true, false, true),
+            ChatGPTEngine::Gpt35Turbo_0301 => EngineInfo::new(4_096, None, false, false, false),
+            ChatGPTEngine::Gpt35Turbo_0613 => EngineInfo::new(4_096, None, true, false, false),
+            ChatGPTEngine::Gpt35Turbo_1106 => {
+                EngineInfo::new(16_385, Some(4_096), true, false, true)
+            }
+            ChatGPTEngine::Gpt35Turbo_0125 => {
+                EngineInfo::new(16_385, Some(4_096), true, false, true)
+            }
+            ChatGPTEngine::Gpt35Turbo_16k => EngineInfo::new(16_385, None, true, false, false),
+            ChatGPTEngine::Gpt4 => EngineInfo::new(8_192, None, true, false, false),
+            ChatGPTEngine::Gpt4_32k => EngineInfo::new(32_768, None, true, false, false),
+            ChatGPTEngine::Gpt4_0314 => EngineInfo::new(8_192, None, false, false, false),
+            ChatGPTEngine::Gpt4_32k_0314 => EngineInfo::new(32_768, None, false, false, false),
+            ChatGPTEngine::Gpt4_0613 => EngineInfo::new(8_192, None, true, false, false),
+            ChatGPTEngine::Gpt4_32k_0613 => EngineInfo::new(32_768, None, true, false, false),
+            ChatGPTEngine::Gpt4Turbo => EngineInfo::new(128_000, Some(4_096), true, true, true),
+            ChatGPTEngine::Gpt4Turbo_Preview => {
+                EngineInfo::new(128_000, Some(4_096), true, false, true)
+            }
+            ChatGPTEngine::Gpt4o => EngineInfo::new(128_000, Some(16_384), true, true, true),
+            ChatGPTEngine::Gpt4oMini => EngineInfo::new(128_000, Some(16_384), true, true, true),
+            ChatGPTEngine::Custom(_) => return None,
+        })
+    }
+
+    /// Maximum amount of tokens in the prompt and the completion combined, if known
+    pub fn context_window(&self) -> Option<u32> {
+        self.info().map(|info| info.context_window)
+    }
+
+    /// Maximum amount of tokens in the completion, if known and limited separately from the context window
+    pub fn max_output_tokens(&self) -> Option<u32> {
+        self.info().and_then(|info| info.max_output_tokens)
+    }
+
+    /// Whether this engine is known to support function calling
+    pub fn supports_functions(&self) -> bool {
+        self.info().is_some_and(|info| info.supports_functions)
+    }
+
+    /// Whether this engine is known to accept images as input
+    pub fn supports_vision(&self) -> bool {
+        self.info().is_some_and(|info| info.supports_vision)
+    }
+
+    /// Whether this engine is known to support the JSON response format
+    pub fn supports_json_mode(&self) -> bool {
+        self.info().is_some_and(|info| info.supports_json_mode)
+    }
 }
 
 impl Display for ChatGPTEngine {
@@ -79,15 +378,159 @@ impl Display for ChatGPTEngine {
 }
 
 impl AsRef<str> for ChatGPTEngine {
-    fn as_ref(&self) -> &'static str {
+    fn as_ref(&self) -> &str {
         match self {
             ChatGPTEngine::Gpt35Turbo => "gpt-3.5-turbo",
             ChatGPTEngine::Gpt35Turbo_0301 => "gpt-3.5-turbo-0301",
+            ChatGPTEngine::Gpt35Turbo_0613 => "gpt-3.5-turbo-0613",
+            ChatGPTEngine::Gpt35Turbo_1106 => "gpt-3.5-turbo-1106",
+            ChatGPTEngine::Gpt35Turbo_0125 => "gpt-3.5-turbo-0125",
+            ChatGPTEngine::Gpt35Turbo_16k => "gpt-3.5-turbo-16k",
             ChatGPTEngine::Gpt4 => "gpt-4",
             ChatGPTEngine::Gpt4_32k => "gpt-4-32k",
             ChatGPTEngine::Gpt4_0314 => "gpt-4-0314",
             ChatGPTEngine::Gpt4_32k_0314 => "gpt-4-32k-0314",
-            ChatGPTEngine::Custom(custom) => custom,
+            ChatGPTEngine::Gpt4_0613 => "gpt-4-0613",
+            ChatGPTEngine::Gpt4_32k_0613 => "gpt-4-32k-0613",
+            ChatGPTEngine::Gpt4Turbo => "gpt-4-turbo",
+            ChatGPTEngine::Gpt4Turbo_Preview => "gpt-4-turbo-preview",
+            ChatGPTEngine::Gpt4o => "gpt-4o",
+            ChatGPTEngine::Gpt4oMini => "gpt-4o-mini",
+            ChatGPTEngine::Custom(custom) => custom.as_ref(),
+        }
+    }
+}
+
+impl FromStr for ChatGPTEngine {
+    type Err = Infallible;
+
+    fn from_str(model: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(model.to_owned()))
+    }
+}
+
+impl From<String> for ChatGPTEngine {
+    /// Resolves a known engine from its model ID, falling back to [`ChatGPTEngine::Custom`]
+    fn from(model: String) -> Self {
+        Self::KNOWN
+            .iter()
+            .find(|engine| engine.as_ref() == model)
+            .cloned()
+            .unwrap_or(ChatGPTEngine::Custom(Cow::Owned(model)))
+    }
+}
+
+impl From<&'static str> for ChatGPTEngine {
+    /// Resolves a known engine from its model ID, falling back to [`ChatGPTEngine::Custom`]
+    fn from(model: &'static str) -> Self {
+        Self::KNOWN
+            .iter()
+            .find(|engine| engine.as_ref() == model)
+            .cloned()
+            .unwrap_or(ChatGPTEngine::Custom(Cow::Borrowed(model)))
+    }
+}
+
+impl Serialize for ChatGPTEngine {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_ref())
+    }
+}
+
+impl<'de> Deserialize<'de> for ChatGPTEngine {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::from(String::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::{ChatGPTEngine, ModelConfiguration};
+
+    #[test]
+    fn test_engine_serialization() {
+        let engines: Vec<ChatGPTEngine> =
+            serde_json::from_str(r#"["gpt-4", "my-fine-tuned-model"]"#).unwrap();
+        assert_eq!(
+            engines,
+            vec![
+                ChatGPTEngine::Gpt4,
+                ChatGPTEngine::Custom("my-fine-tuned-model".into())
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&engines).unwrap(),
+            r#"["gpt-4","my-fine-tuned-model"]"#
+        );
+    }
+
+    #[test]
+    fn test_known_engines_round_trip() {
+        for engine in ChatGPTEngine::KNOWN {
+            assert_eq!(&ChatGPTEngine::from(engine.to_string()), engine);
+            assert!(engine.info().is_some());
         }
+        let custom = ChatGPTEngine::from("my-fine-tuned-model".to_string());
+        assert_eq!(custom, ChatGPTEngine::Custom("my-fine-tuned-model".into()));
+        assert_eq!(custom.info(), None);
+        assert!(!custom.supports_functions());
+    }
+
+    #[test]
+    fn test_fallback_triggers() {
+        use crate::config::FallbackTrigger;
+        use crate::err::{ApiError, Error};
+
+        let context_exceeded = Error::ContextLengthExceeded(ApiError {
+            message: "This model's maximum context length is 8192 tokens".to_string(),
+            error_type: "invalid_request_error".to_string(),
+            code: Some("context_length_exceeded".to_string()),
+            status: 400,
+            request_id: None,
+        });
+        assert!(FallbackTrigger::ContextLengthExceeded.matches(&context_exceeded));
+        assert!(!FallbackTrigger::ServerError.matches(&context_exceeded));
+        assert!(FallbackTrigger::ContextLengthExceeded
+            .accepts(&ChatGPTEngine::Gpt4, &ChatGPTEngine::Gpt4_32k));
+        assert!(!FallbackTrigger::ContextLengthExceeded
+            .accepts(&ChatGPTEngine::Gpt4_32k, &ChatGPTEngine::Gpt4));
+        assert!(
+            FallbackTrigger::ServerError.accepts(&ChatGPTEngine::Gpt4_32k, &ChatGPTEngine::Gpt4)
+        );
+    }
+
+    #[test]
+    fn test_json_configuration() {
+        let config: ModelConfiguration =
+            serde_json::from_str(r#"{ "engine": "gpt-4-32k", "timeout": 2.5 }"#).unwrap();
+        assert_eq!(config.engine, ChatGPTEngine::Gpt4_32k);
+        assert_eq!(config.timeout, Duration::from_millis(2500));
+        assert_eq!(
+            config.temperature,
+            ModelConfiguration::default().temperature
+        );
+    }
+
+    #[test]
+    fn test_toml_configuration() {
+        let config: ModelConfiguration = toml::from_str(
+            r#"
+            engine = "gpt-4"
+            temperature = 0.9
+            max_tokens = 100
+            api_url = "http://localhost:8080/v1/chat/completions"
+            timeout = 30
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.engine, ChatGPTEngine::Gpt4);
+        assert_eq!(config.max_tokens, Some(100));
+        assert_eq!(config.timeout, Duration::from_secs(30));
+        assert_eq!(
+            config.api_url.as_str(),
+            "http://localhost:8080/v1/chat/completions"
+        );
     }
 }
@@ -1,6 +1,8 @@
 use std::path::Path;
+#[cfg(any(feature = "json", feature = "postcard"))]
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use tokio::{fs::File, io::AsyncWriteExt};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 
 #[cfg(feature = "functions")]
 use crate::functions::{
@@ -12,27 +14,152 @@ use std::collections::HashMap;
 #[cfg(feature = "functions")]
 use thiserror::Error;
 #[cfg(feature = "streams")]
-use {crate::types::ResponseChunk, futures::Stream};
+use {
+    crate::moderation::moderate_stream,
+    crate::types::{split_choices, ChoiceStream, ResponseChunk},
+    futures::{ready, Stream},
+    std::pin::Pin,
+    std::task::{Context, Poll},
+};
+#[cfg(feature = "json")]
+use {
+    crate::store::{ConversationStore, Journal},
+    std::sync::Arc,
+};
 
 use crate::{
+    branch::HistoryTree,
     client::ChatGPT,
-    types::{ChatMessage, CompletionResponse, Role},
+    config::RequestOptions,
+    err::Error,
+    moderation::ModerationPolicy,
+    retrieval::{is_context_message, RetrievalPolicy},
+    types::{ChatMessage, CompletionResponse, MessageChoice, MessageMetadata, Role},
 };
 
+/// A streamed reply of a [`Conversation`], that removes the sent message from the history if the stream is aborted.
+///
+/// Requires the `streams` crate feature
+#[cfg(feature = "streams")]
+pub struct ConversationStream<'a> {
+    conversation: &'a mut Conversation,
+    stream: BoxedChunkStream,
+    pushed: usize,
+}
+
+/// A type-erased stream of response chunks
+#[cfg(feature = "streams")]
+type BoxedChunkStream = Pin<Box<dyn Stream<Item = ResponseChunk> + Send>>;
+
+#[cfg(feature = "streams")]
+impl Stream for ConversationStream<'_> {
+    type Item = ResponseChunk;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = ready!(self.stream.as_mut().poll_next(cx));
+        if let Some(ResponseChunk::Aborted { .. }) = chunk {
+            let len = self.conversation.history.len() - std::mem::take(&mut self.pushed);
+            self.conversation.history.truncate(len);
+        }
+        Poll::Ready(chunk)
+    }
+}
+
 /// Stores a single conversation session, and automatically saves message history
 pub struct Conversation {
     pub(crate) client: ChatGPT,
     /// All the messages sent and received, starting with the beginning system message
     pub history: Vec<ChatMessage>,
+    /// If set, user messages (and optionally assistant replies) are moderated before they are added to the history,
+    /// failing with [`Error::Flagged`] if they violate the usage policies
+    pub moderation: Option<ModerationPolicy>,
+    /// If set, passages relevant to each user message are retrieved and added to the history as a system message before it
+    pub retrieval: Option<RetrievalPolicy>,
     /// Set to `true` if you want to automatically send all functions to API with each message.
     ///
     /// Functions are counted as tokens internally, so it is set to `false` by default.
     #[cfg(feature = "functions")]
     pub always_send_functions: bool,
     #[cfg(feature = "functions")]
-    functions: HashMap<String, Box<dyn GptFunctionHolder>>,
+    pub(crate) functions: HashMap<String, Box<dyn GptFunctionHolder>>,
     #[cfg(feature = "functions")]
     function_descriptors: Vec<serde_json::Value>,
+    #[cfg(feature = "json")]
+    store: Option<StoreBinding>,
+    #[cfg(feature = "json")]
+    journal: Option<StoreBinding>,
+    pub(crate) tree: HistoryTree,
+}
+
+/// Picks the index of the reply choice, that is added to the history
+pub(crate) type ChoiceSelector<'a> = &'a mut (dyn FnMut(&[MessageChoice]) -> usize + Send);
+
+/// A [`ConversationStore`] the conversation is persisted to, along with the amount of already persisted messages
+#[cfg(feature = "json")]
+struct StoreBinding {
+    store: Arc<dyn ConversationStore>,
+    id: String,
+    persisted: usize,
+    rewrite: bool,
+}
+
+#[cfg(feature = "json")]
+impl StoreBinding {
+    fn new(store: Arc<dyn ConversationStore>, id: String, persisted: usize) -> Self {
+        Self {
+            store,
+            id,
+            persisted,
+            rewrite: false,
+        }
+    }
+
+    /// Appends the messages added since the last sync, or saves the whole history if it was shortened
+    async fn sync(&mut self, history: &[ChatMessage]) -> crate::Result<()> {
+        if self.rewrite || history.len() < self.persisted {
+            self.store.save(&self.id, history).await?;
+            self.rewrite = false;
+        } else {
+            for message in &history[self.persisted..] {
+                self.store.append(&self.id, message).await?;
+            }
+        }
+        self.persisted = history.len();
+        Ok(())
+    }
+}
+
+/// Writes the data to a temporary file next to the path, and then renames it over the path,
+/// so the file is never left partially written.
+///
+/// The temporary file is unique to the process and the call, so concurrent writes to the same path do not interfere.
+#[cfg(any(feature = "json", feature = "postcard"))]
+pub(crate) async fn write_atomic(path: &Path, data: &[u8]) -> crate::Result<()> {
+    static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = std::path::PathBuf::from(temp);
+    let written = async {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&temp, path).await
+    }
+    .await;
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(&temp).await;
+    }
+    Ok(written?)
 }
 
 impl Conversation {
@@ -40,18 +167,22 @@ impl Conversation {
     pub fn new(client: ChatGPT, first_message: String) -> Self {
         Self {
             client,
-            history: vec![ChatMessage {
-                role: Role::System,
-                content: first_message,
-                #[cfg(feature = "functions")]
-                function_call: None,
-            }],
+            history: vec![
+                ChatMessage::new(Role::System, first_message).with_metadata(MessageMetadata::now())
+            ],
+            moderation: None,
+            retrieval: None,
             #[cfg(feature = "functions")]
             functions: HashMap::with_capacity(4),
             #[cfg(feature = "functions")]
             always_send_functions: false,
             #[cfg(feature = "functions")]
             function_descriptors: Vec::with_capacity(4),
+            #[cfg(feature = "json")]
+            store: None,
+            #[cfg(feature = "json")]
+            journal: None,
+            tree: HistoryTree::default(),
         }
     }
 
@@ -60,20 +191,116 @@ impl Conversation {
         Self {
             client,
             history,
+            moderation: None,
+            retrieval: None,
             #[cfg(feature = "functions")]
             functions: HashMap::with_capacity(4),
             #[cfg(feature = "functions")]
             always_send_functions: false,
             #[cfg(feature = "functions")]
             function_descriptors: Vec::with_capacity(4),
+            #[cfg(feature = "json")]
+            store: None,
+            #[cfg(feature = "json")]
+            journal: None,
+            tree: HistoryTree::default(),
         }
     }
 
-    /// Rollbacks the history by 1 message, removing the last sent and received message.
+    /// Attaches a store the conversation is persisted to under the provided ID, saving the current history to it.
+    ///
+    /// Afterwards, new messages are appended to the store whenever a message is sent. Call [`Self::sync_store()`]
+    /// after modifying the history manually, e.g. after collecting a streamed response.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub async fn attach_store<S: Into<String>>(
+        &mut self,
+        store: Arc<dyn ConversationStore>,
+        id: S,
+    ) -> crate::Result<()> {
+        let id = id.into();
+        store.save(&id, &self.history).await?;
+        self.bind_store(store, id);
+        Ok(())
+    }
+
+    /// Attaches a store that already contains the current history
+    #[cfg(feature = "json")]
+    pub(crate) fn bind_store(&mut self, store: Arc<dyn ConversationStore>, id: String) {
+        self.store = Some(StoreBinding::new(store, id, self.history.len()));
+    }
+
+    /// Attaches a journal that already contains the current history
+    #[cfg(feature = "json")]
+    pub(crate) fn bind_journal(&mut self, journal: Journal) {
+        self.journal = Some(StoreBinding::new(
+            Arc::new(journal),
+            String::new(),
+            self.history.len(),
+        ));
+    }
+
+    /// Persists the messages added since the last sync to the attached store and journal.
+    ///
+    /// If the history was shortened in the meantime, e.g. by a rollback, the whole history is saved instead.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub async fn sync_store(&mut self) -> crate::Result<()> {
+        for binding in [&mut self.store, &mut self.journal].into_iter().flatten() {
+            binding.sync(&self.history).await?;
+        }
+        Ok(())
+    }
+
+    /// Rollbacks the history by 1 turn, removing the last user message and all messages after it,
+    /// e.g. function calls, their results and the reply. The retrieved context of the user message is removed as well.
+    ///
+    /// Returns the last removed message, usually the reply, or `None` if the history contains no user message.
     pub fn rollback(&mut self) -> Option<ChatMessage> {
-        let last = self.history.pop();
-        self.history.pop();
-        last
+        let start = self.turn_start(self.last_user_message()?);
+        self.truncate_history(start).pop()
+    }
+
+    /// Rollbacks the history to the state before the message at the provided index was added,
+    /// removing it and all messages after it. Returns the removed messages.
+    pub fn rollback_to(&mut self, index: usize) -> crate::Result<Vec<ChatMessage>> {
+        self.check_index(index)?;
+        Ok(self.truncate_history(index))
+    }
+
+    /// Returns the index of the last user message in the history
+    pub(crate) fn last_user_message(&self) -> Option<usize> {
+        self.history
+            .iter()
+            .rposition(|message| message.role == Role::User)
+    }
+
+    /// Returns the index of the first message of the turn of the message at the provided index,
+    /// which is the retrieved context preceding it, if there is any
+    pub(crate) fn turn_start(&self, index: usize) -> usize {
+        match index.checked_sub(1) {
+            Some(previous) if previous > 0 && is_context_message(&self.history[previous]) => {
+                previous
+            }
+            _ => index,
+        }
+    }
+
+    /// Truncates the history to the provided length, returning the removed messages.
+    ///
+    /// The attached stores save the whole history on the next sync, as the removed messages may already be persisted.
+    pub(crate) fn truncate_history(&mut self, len: usize) -> Vec<ChatMessage> {
+        let removed = self.history.split_off(len.min(self.history.len()));
+        #[cfg(feature = "json")]
+        for binding in [&mut self.store, &mut self.journal].into_iter().flatten() {
+            if binding.persisted > len {
+                binding.persisted = len;
+                binding.rewrite = true;
+            }
+        }
+        removed
     }
 
     /// Adds a function that can later be called by ChatGPT
@@ -93,38 +320,41 @@ impl Conversation {
     }
 
     /// Sends a message from a specified role to the ChatGPT API and returns the completion response.
-    #[cfg_attr(feature = "functions", async_recursion::async_recursion)]
-    pub async fn send_role_message<S: Into<String> + Send + Sync>(
+    pub async fn send_role_message<S>(
         &mut self,
         role: Role,
         message: S,
-    ) -> crate::Result<CompletionResponse> {
-        self.history.push(ChatMessage {
-            role,
-            content: message.into(),
-            #[cfg(feature = "functions")]
-            function_call: None,
-        });
+    ) -> crate::Result<CompletionResponse>
+    where
+        S: Into<String> + Send + Sync,
+    {
+        self.send_role_message_with_options(role, message, &RequestOptions::default())
+            .await
+    }
 
-        #[cfg(feature = "functions")]
-        let resp = if self.always_send_functions {
-            self.client
-                .send_history_functions(&self.history, &self.function_descriptors)
-                .await?
-        } else {
-            self.client.send_history(&self.history).await?
-        };
-        #[cfg(not(feature = "functions"))]
-        let resp = self.client.send_history(&self.history).await?;
-        let msg = &resp.message_choices[0].message;
-        self.history.push(msg.clone());
-        if let Some(function_response) = self.process_possible_function_response(msg).await {
-            self.history
-                .push(function_response.message_choices[0].message.clone());
-            Ok(function_response)
-        } else {
-            Ok(resp)
-        }
+    /// Sends a message from a specified role to the ChatGPT API, applying the provided per-call options, and returns the completion response.
+    ///
+    /// If the request fails or is cancelled, the sent message is removed from the history again.
+    pub async fn send_role_message_with_options<S>(
+        &mut self,
+        role: Role,
+        message: S,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse>
+    where
+        S: Into<String> + Send + Sync,
+    {
+        let response = self
+            .send_and_process(
+                ChatMessage::new(role, message),
+                self.sends_functions(),
+                options,
+                None,
+            )
+            .await?;
+        #[cfg(feature = "json")]
+        self.sync_store().await?;
+        Ok(response)
     }
 
     /// Sends the message to the ChatGPT API and returns the completion response.
@@ -137,6 +367,18 @@ impl Conversation {
         self.send_role_message(Role::User, message).await
     }
 
+    /// Sends the message to the ChatGPT API, applying the provided per-call options, and returns the completion response.
+    ///
+    /// If the request fails or is cancelled, the sent message is removed from the history again.
+    pub async fn send_message_with_options<S: Into<String> + Send + Sync>(
+        &mut self,
+        message: S,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse> {
+        self.send_role_message_with_options(Role::User, message, options)
+            .await
+    }
+
     /// Sends a message with all functions to the ChatGPT API and returns the completion response.
     ///
     /// **NOTE**: Functions are counted as tokens internally.
@@ -145,27 +387,252 @@ impl Conversation {
         &mut self,
         message: S,
     ) -> crate::Result<CompletionResponse> {
-        self.history.push(ChatMessage {
-            role: Role::User,
-            content: message.into(),
-            #[cfg(feature = "functions")]
-            function_call: None,
-        });
-        let resp = self
-            .client
-            .send_history_functions(&self.history, &self.function_descriptors)
+        let response = self
+            .send_and_process(
+                ChatMessage::new(Role::User, message),
+                true,
+                &RequestOptions::default(),
+                None,
+            )
+            .await?;
+        #[cfg(feature = "json")]
+        self.sync_store().await?;
+        Ok(response)
+    }
+
+    /// Whether functions are sent with regular messages
+    pub(crate) fn sends_functions(&self) -> bool {
+        #[cfg(feature = "functions")]
+        {
+            self.always_send_functions
+        }
+        #[cfg(not(feature = "functions"))]
+        false
+    }
+
+    /// Pushes the message to the history, requests the completion and processes possible function calls
+    pub(crate) async fn send_and_process(
+        &mut self,
+        message: ChatMessage,
+        send_functions: bool,
+        options: &RequestOptions,
+        selector: Option<ChoiceSelector<'_>>,
+    ) -> crate::Result<CompletionResponse> {
+        let pushed = self.push_message(message).await?;
+        self.complete_and_process(pushed, send_functions, options, selector)
+            .await
+    }
+
+    /// Sends the message to the ChatGPT API and lets the selector pick which of the reply choices is added to the history.
+    ///
+    /// Useful with [`ModelConfiguration::reply_count`](crate::config::ModelConfiguration::reply_count) set to more than 1.
+    /// The selector is called with all choices and returns the index of the selected one, failing with [`Error::InvalidChoice`]
+    /// if it is out of bounds. The other choices are kept as alternative branches, see [`Self::alternatives()`].
+    /// The returned response contains all choices.
+    pub async fn send_message_with_selector<S, F>(
+        &mut self,
+        message: S,
+        selector: F,
+    ) -> crate::Result<CompletionResponse>
+    where
+        S: Into<String> + Send + Sync,
+        F: FnMut(&[MessageChoice]) -> usize + Send,
+    {
+        self.send_role_message_with_selector(
+            Role::User,
+            message,
+            &RequestOptions::default(),
+            selector,
+        )
+        .await
+    }
+
+    /// Sends a message from a specified role to the ChatGPT API, applying the provided per-call options,
+    /// and lets the selector pick which of the reply choices is added to the history.
+    ///
+    /// See [`Self::send_message_with_selector()`] for details.
+    pub async fn send_role_message_with_selector<S, F>(
+        &mut self,
+        role: Role,
+        message: S,
+        options: &RequestOptions,
+        mut selector: F,
+    ) -> crate::Result<CompletionResponse>
+    where
+        S: Into<String> + Send + Sync,
+        F: FnMut(&[MessageChoice]) -> usize + Send,
+    {
+        let response = self
+            .send_and_process(
+                ChatMessage::new(role, message),
+                self.sends_functions(),
+                options,
+                Some(&mut selector),
+            )
             .await?;
-        let msg = &resp.message_choices[0].message;
-        self.history.push(msg.clone());
-        if let Some(function_response) = self.process_possible_function_response(msg).await {
-            self.history
-                .push(function_response.message_choices[0].message.clone());
+        #[cfg(feature = "json")]
+        self.sync_store().await?;
+        Ok(response)
+    }
+
+    /// Adds the selected one of the reply choices to the history, keeping the other ones as alternative branches.
+    ///
+    /// Use this to commit a reply received as multiple streamed choices, e.g. from [`Self::send_message_streaming_choices()`].
+    /// Fails with [`Error::InvalidChoice`] if the selected index is out of bounds.
+    pub fn commit_choice(
+        &mut self,
+        choices: Vec<ChatMessage>,
+        selected: usize,
+    ) -> crate::Result<()> {
+        if selected >= choices.len() {
+            return Err(Error::InvalidChoice {
+                index: selected,
+                count: choices.len(),
+            });
+        }
+        let choices = choices
+            .into_iter()
+            .map(|mut choice| {
+                choice.metadata.get_or_insert_with(MessageMetadata::now);
+                choice
+            })
+            .collect();
+        self.push_choice(choices, selected);
+        Ok(())
+    }
+
+    /// Pushes the selected choice to the history, recording the other ones in the tree
+    fn push_choice(&mut self, mut choices: Vec<ChatMessage>, selected: usize) {
+        let reply = choices.remove(selected);
+        for alternative in choices {
+            self.history.push(alternative);
+            self.tree.record(&self.history);
+            self.history.pop();
+        }
+        self.history.push(reply);
+        if !self.tree.is_empty() {
+            self.tree.record(&self.history);
+        }
+    }
+
+    /// Requests the completion of the current history, pushes the reply and processes possible function calls.
+    ///
+    /// If the request fails, the last `pushed` messages are removed from the history again.
+    pub(crate) async fn complete_and_process(
+        &mut self,
+        pushed: usize,
+        send_functions: bool,
+        options: &RequestOptions,
+        selector: Option<ChoiceSelector<'_>>,
+    ) -> crate::Result<CompletionResponse> {
+        let resp = match self.request_completion(send_functions, options).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                self.history.truncate(self.history.len() - pushed);
+                return Err(err);
+            }
+        };
+        let selected = selector.map_or(0, |select| select(&resp.message_choices));
+        let Some(choice) = resp.message_choices.get(selected) else {
+            self.history.truncate(self.history.len() - pushed);
+            return Err(Error::InvalidChoice {
+                index: selected,
+                count: resp.message_choices.len(),
+            });
+        };
+        let msg = &choice.message;
+        if self
+            .moderation
+            .as_ref()
+            .is_some_and(|policy| policy.moderate_replies)
+        {
+            if let Err(err) = self.moderate(msg).await {
+                self.history.truncate(self.history.len() - pushed);
+                return Err(err);
+            }
+        }
+        let choices = resp
+            .message_choices
+            .iter()
+            .enumerate()
+            .map(|(index, choice)| {
+                choice
+                    .message
+                    .clone()
+                    .with_metadata(MessageMetadata::from_response(&resp, index))
+            })
+            .collect();
+        self.push_choice(choices, selected);
+        if let Some(function_response) = self.process_possible_function_response(msg, options).await
+        {
             Ok(function_response)
         } else {
             Ok(resp)
         }
     }
 
+    /// Pushes the message to the history, returning the amount of pushed messages.
+    ///
+    /// User messages are moderated first, and preceded by the retrieved context if the policies are set.
+    async fn push_message(&mut self, mut message: ChatMessage) -> crate::Result<usize> {
+        message.metadata.get_or_insert_with(MessageMetadata::now);
+        if message.role != Role::User {
+            self.history.push(message);
+            return Ok(1);
+        }
+        self.moderate(&message).await?;
+        let context = match &self.retrieval {
+            Some(policy) => policy.context_message(&message.content).await?,
+            None => None,
+        };
+        let pushed = match context {
+            Some(context) => {
+                self.history.push(context);
+                2
+            }
+            None => 1,
+        };
+        self.history.push(message);
+        Ok(pushed)
+    }
+
+    /// Moderates the message if a moderation policy is set, failing if it was flagged
+    async fn moderate(&self, message: &ChatMessage) -> crate::Result<()> {
+        let Some(policy) = &self.moderation else {
+            return Ok(());
+        };
+        let response = self
+            .client
+            .moderate_many(
+                std::slice::from_ref(&message.content),
+                policy.model.as_deref(),
+            )
+            .await?;
+        match response.results.into_iter().find(|result| result.flagged) {
+            Some(result) => Err(Error::Flagged {
+                role: message.role,
+                result: Box::new(result),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Requests the completion of the current history
+    #[cfg_attr(not(feature = "functions"), allow(unused_variables))]
+    async fn request_completion(
+        &self,
+        send_functions: bool,
+        options: &RequestOptions,
+    ) -> crate::Result<CompletionResponse> {
+        #[allow(unused_mut)]
+        let mut request = self.client.completion_request(&self.history, false);
+        #[cfg(feature = "functions")]
+        if send_functions {
+            request.functions = &self.function_descriptors;
+        }
+        self.client.complete(&request, options).await
+    }
+
     /// Sends a message with specified role to the ChatGPT API and returns the completion response as stream.
     ///
     /// Note, that this method will not automatically save the received message to history, as
@@ -179,15 +646,78 @@ impl Conversation {
         &mut self,
         role: Role,
         message: S,
-    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
-        self.history.push(ChatMessage {
-            role,
-            content: message.into(),
-            #[cfg(feature = "functions")]
-            function_call: None,
-        });
-        let stream = self.client.send_history_streaming(&self.history).await?;
-        Ok(stream)
+    ) -> crate::Result<ConversationStream<'_>> {
+        self.send_role_message_streaming_with_options(role, message, &RequestOptions::default())
+            .await
+    }
+
+    /// Sends a message with specified role to the ChatGPT API, applying the provided per-call options, and returns the completion response as stream.
+    ///
+    /// If the request fails before the response starts, the sent message (and the retrieved context) is removed from the history again.
+    /// If the moderation policy includes replies, each reply is moderated once it is complete, see [`ModerationPolicy::moderate_replies`].
+    /// If the stream is aborted afterwards, it ends with a [`ResponseChunk::Aborted`] chunk and the sent message is removed as well.
+    /// Dropping the stream before it ends keeps the sent message, use [`Self::rollback()`] to remove it.
+    ///
+    /// Requires the `streams` crate feature.
+    #[cfg(feature = "streams")]
+    pub async fn send_role_message_streaming_with_options<S: Into<String>>(
+        &mut self,
+        role: Role,
+        message: S,
+        options: &RequestOptions,
+    ) -> crate::Result<ConversationStream<'_>> {
+        let (stream, pushed) = self.start_streaming(role, message.into(), options).await?;
+        Ok(ConversationStream {
+            conversation: self,
+            stream,
+            pushed,
+        })
+    }
+
+    /// Pushes the message and starts streaming the reply, returning the stream and the amount of pushed messages
+    #[cfg(feature = "streams")]
+    async fn start_streaming(
+        &mut self,
+        role: Role,
+        message: String,
+        options: &RequestOptions,
+    ) -> crate::Result<(BoxedChunkStream, usize)> {
+        let pushed = self.push_message(ChatMessage::new(role, message)).await?;
+        match self
+            .client
+            .send_history_streaming_with_options(&self.history, options)
+            .await
+        {
+            Ok(stream) => match self
+                .moderation
+                .as_ref()
+                .filter(|policy| policy.moderate_replies)
+            {
+                Some(policy) => {
+                    let client = self.client.clone();
+                    let model = policy.model.clone();
+                    let stream = moderate_stream(stream, move |reply| {
+                        let client = client.clone();
+                        let model = model.clone();
+                        async move {
+                            // Replies that cannot be moderated are treated like flagged ones
+                            client
+                                .moderate_many(&[reply], model.as_deref())
+                                .await
+                                .map_or(true, |response| {
+                                    response.results.iter().any(|result| result.flagged)
+                                })
+                        }
+                    });
+                    Ok((Box::pin(stream), pushed))
+                }
+                None => Ok((Box::pin(stream), pushed)),
+            },
+            Err(err) => {
+                self.history.truncate(self.history.len() - pushed);
+                Err(err)
+            }
+        }
     }
 
     /// Sends the message to the ChatGPT API and returns the completion response as stream.
@@ -202,32 +732,88 @@ impl Conversation {
     pub async fn send_message_streaming<S: Into<String>>(
         &mut self,
         message: S,
-    ) -> crate::Result<impl Stream<Item = ResponseChunk>> {
+    ) -> crate::Result<ConversationStream<'_>> {
         self.send_role_message_streaming(Role::User, message).await
     }
 
+    /// Sends the message to the ChatGPT API, applying the provided per-call options, and returns the completion response as stream.
+    ///
+    /// See [`Self::send_role_message_streaming_with_options()`] for details.
+    ///
+    /// Requires the `streams` crate feature.
+    #[cfg(feature = "streams")]
+    pub async fn send_message_streaming_with_options<S: Into<String>>(
+        &mut self,
+        message: S,
+        options: &RequestOptions,
+    ) -> crate::Result<ConversationStream<'_>> {
+        self.send_role_message_streaming_with_options(Role::User, message, options)
+            .await
+    }
+
+    /// Sends a message with specified role to the ChatGPT API, applying the provided per-call options, and returns a separate
+    /// stream for each of the reply choices, see [`split_choices`].
+    ///
+    /// Useful with [`ModelConfiguration::reply_count`](crate::config::ModelConfiguration::reply_count) set to more than 1.
+    /// The replies are not added to the history automatically, collect them with [`ChatMessage::from_response_chunks`]
+    /// and add the selected one with [`Self::commit_choice()`] once the streams are dropped.
+    /// If the streams are aborted, the sent message is removed from the history, like with [`ConversationStream`].
+    ///
+    /// Requires the `streams` crate feature.
+    #[cfg(feature = "streams")]
+    pub async fn send_role_message_streaming_choices<S: Into<String>>(
+        &mut self,
+        role: Role,
+        message: S,
+        options: &RequestOptions,
+    ) -> crate::Result<Vec<ChoiceStream<'_>>> {
+        let count = self.client.config.reply_count.max(1) as usize;
+        let stream = self
+            .send_role_message_streaming_with_options(role, message, options)
+            .await?;
+        Ok(split_choices(stream, count))
+    }
+
+    /// Sends the message to the ChatGPT API and returns a separate stream for each of the reply choices.
+    ///
+    /// See [`Self::send_role_message_streaming_choices()`] for details.
+    ///
+    /// Requires the `streams` crate feature.
+    #[cfg(feature = "streams")]
+    pub async fn send_message_streaming_choices<S: Into<String>>(
+        &mut self,
+        message: S,
+    ) -> crate::Result<Vec<ChoiceStream<'_>>> {
+        self.send_role_message_streaming_choices(Role::User, message, &RequestOptions::default())
+            .await
+    }
+
     /// Saves the history to a local JSON file, that can be restored to a conversation at runtime later.
+    ///
+    /// The file is replaced atomically, so the previously saved history is kept if saving fails midway.
     #[cfg(feature = "json")]
     pub async fn save_history_json<P: AsRef<Path>>(&self, to: P) -> crate::Result<()> {
-        let path = to.as_ref();
-        if path.exists() {
-            tokio::fs::remove_file(path).await?;
-        }
-        let mut file = File::create(path).await?;
-        file.write_all(&serde_json::to_vec(&self.history)?).await?;
-        Ok(())
+        write_atomic(to.as_ref(), &serde_json::to_vec(&self.history)?).await
     }
 
     /// Saves the history to a local postcard file, that can be restored to a conversation at runtime later.
+    ///
+    /// The file is replaced atomically, so the previously saved history is kept if saving fails midway.
+    /// The file starts with a format version, so the history including the message metadata can be read by later versions.
     #[cfg(feature = "postcard")]
     pub async fn save_history_postcard<P: AsRef<Path>>(&self, to: P) -> crate::Result<()> {
-        let path = to.as_ref();
-        if path.exists() {
-            tokio::fs::remove_file(path).await?;
-        }
-        let mut file = File::create(path).await?;
-        file.write_all(&postcard::to_allocvec(&self.history)?)
-            .await?;
+        write_atomic(to.as_ref(), &crate::types::to_postcard(&self.history)?).await
+    }
+
+    /// Saves the history to a local JSON Lines journal, and keeps appending new messages to it whenever a message is sent.
+    ///
+    /// The journal can be restored with [`ChatGPT::restore_conversation_journal()`], which tolerates a truncated
+    /// last line, e.g. after a crash. Call [`Self::sync_store()`] after modifying the history manually.
+    #[cfg(feature = "json")]
+    pub async fn save_history_journal<P: AsRef<Path>>(&mut self, to: P) -> crate::Result<()> {
+        let journal = Journal::new(to.as_ref());
+        journal.save("", &self.history).await?;
+        self.bind_journal(journal);
         Ok(())
     }
 
@@ -235,6 +821,7 @@ impl Conversation {
     async fn process_possible_function_response(
         &mut self,
         _message: &ChatMessage,
+        _options: &RequestOptions,
     ) -> Option<CompletionResponse> {
         None
     }
@@ -243,9 +830,10 @@ impl Conversation {
     async fn process_possible_function_response(
         &mut self,
         message: &ChatMessage,
+        options: &RequestOptions,
     ) -> Option<CompletionResponse> {
         if let Some(call) = &message.function_call {
-            if let Some(Ok(result)) = self.process_function(call).await {
+            if let Some(Ok(result)) = self.process_function(call, options).await {
                 Some(result)
             } else {
                 None
@@ -257,15 +845,17 @@ impl Conversation {
 
     // TODO: streamed function processing is technically possible
     #[cfg(feature = "functions")]
+    #[async_recursion::async_recursion]
     async fn process_function(
         &mut self,
         call: &FunctionCall,
+        options: &RequestOptions,
     ) -> Option<crate::Result<CompletionResponse>> {
         let call_result = if let Some(fnc) = self.functions.get(&call.name) {
             // TODO: better error handling?
             // TODO: maybe replace check for SerdeJsonError with a special error?
             fnc.try_invoke(&call.arguments).await.map_err(|err| {
-                if let crate::err::Error::SerdeJsonError(_) = err {
+                if let Error::SerdeJsonError(_) = err {
                     FunctionCallError::InvalidArguments
                 } else {
                     FunctionCallError::InnerError(err.to_string())
@@ -276,14 +866,21 @@ impl Conversation {
         };
         if let Ok(result) = call_result {
             let result = serde_json::to_string(&result);
-            return Some(self.send_role_message(Role::Function, result.ok()?).await);
+            return Some(
+                self.send_role_message_with_options(Role::Function, result.ok()?, options)
+                    .await,
+            );
         }
 
         if self.client.config.function_validation == FunctionValidationStrategy::Strict {
             // Sending error response from function
             Some(
-                self.send_role_message(Role::System, call_result.unwrap_err().to_string())
-                    .await,
+                self.send_role_message_with_options(
+                    Role::System,
+                    call_result.unwrap_err().to_string(),
+                    options,
+                )
+                .await,
             )
         } else {
             None
@@ -301,3 +898,82 @@ enum FunctionCallError {
     #[error("Exception encountered when calling function: {0}")]
     InnerError(String),
 }
+
+#[cfg(all(test, feature = "streams"))]
+mod tests {
+    use futures::StreamExt;
+
+    use crate::client::ChatGPT;
+    use crate::converse::ConversationStream;
+    use crate::types::{split_choices, AbortReason, ChatMessage, ResponseChunk, Role};
+
+    #[tokio::test]
+    async fn test_stream_rollback() {
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello"));
+
+        let chunks = vec![
+            ResponseChunk::Content {
+                delta: "Hi".to_string(),
+                response_index: 0,
+            },
+            ResponseChunk::Aborted {
+                reason: AbortReason::Cancelled,
+            },
+        ];
+        let stream = ConversationStream {
+            conversation: &mut conversation,
+            stream: Box::pin(futures::stream::iter(chunks.clone())),
+            pushed: 1,
+        };
+        assert_eq!(stream.collect::<Vec<_>>().await, chunks);
+        assert_eq!(conversation.history.len(), 1);
+
+        // Completed streams keep the sent message
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello"));
+        let stream = ConversationStream {
+            conversation: &mut conversation,
+            stream: Box::pin(futures::stream::iter([ResponseChunk::Done])),
+            pushed: 1,
+        };
+        stream.collect::<Vec<_>>().await;
+        assert_eq!(conversation.history.len(), 2);
+
+        // Aborted choice streams remove the sent message as well
+        let stream = ConversationStream {
+            conversation: &mut conversation,
+            stream: Box::pin(futures::stream::iter(chunks)),
+            pushed: 1,
+        };
+        let streams = split_choices(stream, 2);
+        futures::future::join_all(streams.into_iter().map(|stream| stream.collect::<Vec<_>>()))
+            .await;
+        assert_eq!(conversation.history.len(), 1);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_concurrent_atomic_writes() {
+        use crate::converse::write_atomic;
+
+        let directory = std::env::temp_dir().join(format!("chatgpt-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("history.json");
+        let (first, second) = futures::join!(
+            write_atomic(&path, b"first"),
+            write_atomic(&path, b"second")
+        );
+        first.unwrap();
+        second.unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content == "first" || content == "second");
+        // No temporary files are left behind
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
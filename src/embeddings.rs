@@ -0,0 +1,287 @@
+use base64::Engine;
+use derive_builder::Builder;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::client::{decode_response, ChatGPT};
+use crate::err::Error;
+
+/// Format in which the embeddings are transferred. The returned vectors are decoded either way.
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingEncoding {
+    /// Vectors are transferred as JSON arrays of floats
+    #[default]
+    Float,
+    /// Vectors are transferred as base64 encoded little-endian floats, which is more compact
+    Base64,
+}
+
+/// Options for the `/v1/embeddings` endpoint
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(default, setter(into))]
+pub struct EmbeddingOptions {
+    /// The embedding model, e.g. `text-embedding-3-small`
+    pub model: String,
+    /// Number of dimensions of the returned vectors. Only supported by `text-embedding-3` and later models.
+    #[builder(setter(strip_option))]
+    pub dimensions: Option<u32>,
+    /// Format in which the embeddings are transferred
+    pub encoding_format: EmbeddingEncoding,
+    /// Maximum amount of inputs sent in a single request. Larger input lists are split into multiple requests.
+    pub batch_size: usize,
+}
+
+impl Default for EmbeddingOptions {
+    fn default() -> Self {
+        Self {
+            model: "text-embedding-3-small".to_string(),
+            dimensions: None,
+            encoding_format: EmbeddingEncoding::Float,
+            batch_size: 2048,
+        }
+    }
+}
+
+/// A single embedding vector
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Embedding {
+    /// Index of the input this embedding belongs to
+    pub index: usize,
+    /// The embedding vector
+    pub vector: Vec<f32>,
+}
+
+impl Embedding {
+    /// Computes the cosine similarity to another embedding
+    pub fn cosine_similarity(&self, other: &Embedding) -> f32 {
+        cosine_similarity(&self.vector, &other.vector)
+    }
+
+    /// Scales the vector to unit length
+    pub fn normalize(&mut self) {
+        normalize(&mut self.vector)
+    }
+}
+
+/// Token usage of an embeddings request
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub struct EmbeddingUsage {
+    /// Tokens in the inputs
+    pub prompt_tokens: u32,
+    /// Total amount of tokens used
+    pub total_tokens: u32,
+}
+
+/// Embeddings returned for a list of inputs
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct EmbeddingResponse {
+    /// The model that created the embeddings
+    pub model: String,
+    /// Embeddings for each of the inputs, in the same order
+    pub data: Vec<Embedding>,
+    /// Token usage, summed over all requests if the inputs were split
+    pub usage: EmbeddingUsage,
+}
+
+impl EmbeddingResponse {
+    /// Returns just the vectors, in the order of the inputs
+    pub fn vectors(&self) -> Vec<&[f32]> {
+        self.data
+            .iter()
+            .map(|embedding| embedding.vector.as_slice())
+            .collect()
+    }
+
+    /// Appends the embeddings of a single batch, offsetting their indices by the position of the batch
+    fn merge(&mut self, response: RawEmbeddingResponse, offset: usize) -> crate::Result<()> {
+        self.model = response.model;
+        self.usage.prompt_tokens += response.usage.prompt_tokens;
+        self.usage.total_tokens += response.usage.total_tokens;
+        for embedding in response.data {
+            self.data.push(Embedding {
+                index: offset + embedding.index,
+                vector: embedding.embedding.decode()?,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Computes the cosine similarity of two vectors, which is `0.0` if either of them is a zero vector
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Scales the vector to unit length. Zero vectors are left unchanged.
+pub fn normalize(vector: &mut [f32]) {
+    let norm = norm(vector);
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|value| value * value).sum::<f32>().sqrt()
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    input: &'a [String],
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+    encoding_format: EmbeddingEncoding,
+}
+
+#[derive(Deserialize)]
+struct RawEmbeddingResponse {
+    model: String,
+    data: Vec<RawEmbedding>,
+    usage: EmbeddingUsage,
+}
+
+#[derive(Deserialize)]
+struct RawEmbedding {
+    index: usize,
+    embedding: RawVector,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+impl RawVector {
+    fn decode(self) -> crate::Result<Vec<f32>> {
+        match self {
+            RawVector::Float(vector) => Ok(vector),
+            RawVector::Base64(encoded) => {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|err| Error::ParsingError(err.to_string()))?;
+                if bytes.len() % 4 != 0 {
+                    return Err(Error::ParsingError(
+                        "Base64 embedding is not a sequence of 32-bit floats".to_string(),
+                    ));
+                }
+                Ok(bytes
+                    .chunks_exact(4)
+                    .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect())
+            }
+        }
+    }
+}
+
+impl ChatGPT {
+    /// Creates embeddings for the inputs with the default [`EmbeddingOptions`]
+    pub async fn embed<I, S>(&self, inputs: I) -> crate::Result<EmbeddingResponse>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.embed_with_options(inputs, &EmbeddingOptions::default())
+            .await
+    }
+
+    /// Creates embeddings for the inputs using the `/v1/embeddings` endpoint.
+    ///
+    /// Inputs exceeding the `batch_size` of the options are split into multiple sequential requests.
+    pub async fn embed_with_options<I, S>(
+        &self,
+        inputs: I,
+        options: &EmbeddingOptions,
+    ) -> crate::Result<EmbeddingResponse>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        if options.batch_size == 0 {
+            return Err(Error::ParsingError(
+                "Embedding batch size must be greater than zero".to_string(),
+            ));
+        }
+        let inputs: Vec<String> = inputs.into_iter().map(Into::into).collect();
+        let mut merged = EmbeddingResponse {
+            model: options.model.clone(),
+            data: Vec::with_capacity(inputs.len()),
+            usage: EmbeddingUsage::default(),
+        };
+        for (batch, chunk) in inputs.chunks(options.batch_size).enumerate() {
+            let response = self
+                .request(Method::POST, self.endpoint("embeddings")?)
+                .json(&EmbeddingRequest {
+                    input: chunk,
+                    model: &options.model,
+                    dimensions: options.dimensions,
+                    encoding_format: options.encoding_format,
+                })
+                .send()
+                .await?;
+            let response: RawEmbeddingResponse = decode_response(response).await?;
+            merged.merge(response, batch * options.batch_size)?;
+        }
+        merged.data.sort_by_key(|embedding| embedding.index);
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embeddings::{
+        cosine_similarity, normalize, EmbeddingResponse, EmbeddingUsage, RawEmbeddingResponse,
+    };
+
+    #[test]
+    fn test_vector_helpers() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+
+        let mut vector = [3.0, 4.0];
+        normalize(&mut vector);
+        assert_eq!(vector, [0.6, 0.8]);
+    }
+
+    #[test]
+    fn test_merge_batches() {
+        let mut merged = EmbeddingResponse {
+            model: String::new(),
+            data: vec![],
+            usage: EmbeddingUsage::default(),
+        };
+        // [1.0, -2.0] as little-endian floats
+        let first: RawEmbeddingResponse = serde_json::from_str(
+            r#"{
+                "model": "text-embedding-3-small",
+                "data": [{ "index": 0, "embedding": "AACAPwAAAMA=" }],
+                "usage": { "prompt_tokens": 3, "total_tokens": 3 }
+            }"#,
+        )
+        .unwrap();
+        let second: RawEmbeddingResponse = serde_json::from_str(
+            r#"{
+                "model": "text-embedding-3-small",
+                "data": [{ "index": 0, "embedding": [0.5, 0.25] }],
+                "usage": { "prompt_tokens": 2, "total_tokens": 2 }
+            }"#,
+        )
+        .unwrap();
+        merged.merge(first, 0).unwrap();
+        merged.merge(second, 1).unwrap();
+        assert_eq!(merged.vectors(), vec![&[1.0, -2.0][..], &[0.5, 0.25][..]]);
+        assert_eq!(merged.data[1].index, 1);
+        assert_eq!(merged.usage.total_tokens, 5);
+    }
+}
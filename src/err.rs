@@ -1,17 +1,23 @@
-use std::{env::VarError, string::FromUtf8Error};
+use std::{env::VarError, fmt, string::FromUtf8Error, time::Duration};
 
 use reqwest::header::InvalidHeaderValue;
 use thiserror::Error;
 
+use crate::moderation::ModerationResult;
+use crate::types::Role;
+
 /// An error enum, used in the Result
 #[derive(Debug, Error)]
 pub enum Error {
-    /// A reqwest-provoked error has occurred
+    /// A reqwest-provoked error has occurred. Timeouts are reported as [`Error::Timeout`] instead.
     #[error("An error occurred when processing a request: {0}")]
-    ClientError(#[from] reqwest::Error),
+    ClientError(reqwest::Error),
     /// Invalid header configuration error. Probably because of the custom User-Agent header
     #[error("Invalid configuration provided: {0}")]
     InvalidConfiguration(#[from] InvalidHeaderValue),
+    /// The [`ChatGPTBuilder`](crate::client::ChatGPTBuilder) was provided with invalid or conflicting options
+    #[error("Failed to build the client: {0}")]
+    BuilderError(String),
     /// An error that occurred when parsing data, e.g. a UUID
     #[error("Parsing error has occurred: {0}")]
     ParsingError(String),
@@ -23,17 +29,112 @@ pub enum Error {
     #[error("Failed to (de)serialize data: {0}")]
     #[cfg(feature = "postcard")]
     PostcardError(#[from] postcard::Error),
+    /// A TOML deserialization error has occurred
+    #[error("Failed to deserialize TOML data: {0}")]
+    #[cfg(feature = "toml")]
+    TomlError(#[from] toml::de::Error),
+    /// A SQLite error has occurred in the [`SqliteStore`](crate::store::SqliteStore)
+    #[error("SQLite error has occurred: {0}")]
+    #[cfg(feature = "sqlite")]
+    SqliteError(#[from] rusqlite::Error),
     /// An error has occurred when parsing a string from UTF-8 bytes
     #[error("Failed to parse string from UTF-8: {0}")]
     StringError(#[from] FromUtf8Error),
-    /// An error on the backend happened
+    /// An error on the backend happened, which is not covered by the more specific variants
     #[error("An error (type: {error_type}) occurred on the API backend: {message}")]
     BackendError {
         /// Message, describing this error
         message: String,
         /// The type of error
         error_type: String,
+        /// Machine-readable error code, if provided
+        code: Option<String>,
+        /// HTTP status code of the response, if known
+        status: Option<u16>,
+        /// Unique ID of the request, if provided
+        request_id: Option<String>,
+    },
+    /// The API key is invalid or missing
+    #[error("Authentication failed: {0}")]
+    Authentication(ApiError),
+    /// The API key is not permitted to access the resource
+    #[error("Permission denied: {0}")]
+    PermissionDenied(ApiError),
+    /// The requested resource, e.g. the model, does not exist
+    #[error("Not found: {0}")]
+    NotFound(ApiError),
+    /// A rate limit or the quota was exceeded
+    #[error("Rate limited: {error}")]
+    RateLimited {
+        /// Details of the error
+        error: ApiError,
+        /// How long to wait before retrying, from the `retry-after` headers
+        retry_after: Option<Duration>,
+    },
+    /// The messages and the completion do not fit into the context window of the model
+    #[error("Context length exceeded: {0}")]
+    ContextLengthExceeded(ApiError),
+    /// The input or the output was rejected by the content filter
+    #[error("Content filtered: {0}")]
+    ContentFiltered(ApiError),
+    /// The API failed to process the request, e.g. because it is overloaded
+    #[error("Server error (status: {status}): {error}")]
+    ServerError {
+        /// HTTP status code of the response
+        status: u16,
+        /// Details of the error
+        error: ApiError,
+    },
+    /// A message was flagged by the moderation of a [`Conversation`](crate::converse::Conversation), and was not added to the history
+    #[error("The {role:?} message was flagged by moderation (categories: {categories:?})", categories = result.flagged_categories())]
+    Flagged {
+        /// Role of the flagged message
+        role: Role,
+        /// The moderation result
+        result: Box<ModerationResult>,
     },
+    /// A [`ConversationSnapshot`](crate::snapshot::ConversationSnapshot) has an unsupported format version
+    #[error("Unsupported conversation snapshot version: {0}")]
+    UnsupportedSnapshotVersion(u32),
+    /// A [`ConversationSnapshot`](crate::snapshot::ConversationSnapshot) requires functions, that are not registered on the conversation
+    #[error("The conversation snapshot requires unregistered functions: {0:?}")]
+    MissingFunctions(Vec<String>),
+    /// The index does not point to a message in the history of a [`Conversation`](crate::converse::Conversation)
+    #[error("Message index {index} is out of bounds for a history of {len} messages")]
+    HistoryIndexOutOfBounds {
+        /// The invalid index
+        index: usize,
+        /// Length of the history
+        len: usize,
+    },
+    /// The history of a [`Conversation`](crate::converse::Conversation) contains no user message to reply to
+    #[error("The history contains no user message to reply to")]
+    NoUserMessage,
+    /// The selected reply choice does not exist
+    #[error("Reply choice {index} is out of bounds for {count} choices")]
+    InvalidChoice {
+        /// The selected index
+        index: usize,
+        /// The amount of choices
+        count: usize,
+    },
+    /// The ID does not point to a message in the [`HistoryTree`](crate::branch::HistoryTree) of a conversation
+    #[error("Unknown branch: {0}")]
+    UnknownBranch(usize),
+    /// An [`Embedder`](crate::retrieval::Embedder) returned a different amount of vectors than it was given inputs
+    #[error("Expected {expected} embedding vectors, got {actual}")]
+    EmbeddingCountMismatch {
+        /// The amount of inputs
+        expected: usize,
+        /// The amount of returned vectors
+        actual: usize,
+    },
+    /// The request was cancelled with a [`CancellationToken`](crate::config::CancellationToken)
+    #[error("The request was cancelled")]
+    Cancelled,
+    /// The request did not complete in time
+    #[error("The request timed out")]
+    Timeout,
     /// A Tokio IO error happened
     #[error("Error happened during an IO operation: {0}")]
     IOError(#[from] tokio::io::Error),
@@ -41,3 +142,194 @@ pub enum Error {
     #[error("Error while trying to access an environment variable: {0}")]
     VarError(#[from] VarError),
 }
+
+/// Details of an error returned by the API
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ApiError {
+    /// Message, describing this error
+    pub message: String,
+    /// The type of error, e.g. `invalid_request_error`
+    pub error_type: String,
+    /// Machine-readable error code, if provided, e.g. `context_length_exceeded`
+    pub code: Option<String>,
+    /// HTTP status code of the response
+    pub status: u16,
+    /// Unique ID of the request, from the `x-request-id` header
+    pub request_id: Option<String>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (type: {}", self.message, self.error_type)?;
+        if let Some(code) = &self.code {
+            write!(f, ", code: {code}")?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, ", request id: {request_id}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::ClientError(err)
+        }
+    }
+}
+
+impl Error {
+    /// Classifies an error returned by the API by its code, type and HTTP status
+    pub fn from_api(error: ApiError, retry_after: Option<Duration>) -> Self {
+        let code = error.code.as_deref();
+        match (code, error.error_type.as_str(), error.status) {
+            (Some("context_length_exceeded"), _, _) => Error::ContextLengthExceeded(error),
+            (Some("content_filter" | "content_policy_violation"), _, _)
+            | (_, "content_filter", _) => Error::ContentFiltered(error),
+            (Some("invalid_api_key"), _, _) | (_, _, 401) => Error::Authentication(error),
+            (_, _, 403) => Error::PermissionDenied(error),
+            (Some("model_not_found"), _, _) | (_, _, 404) => Error::NotFound(error),
+            (Some("rate_limit_exceeded" | "insufficient_quota"), _, _)
+            | (_, "insufficient_quota", _)
+            | (_, _, 429) => Error::RateLimited { error, retry_after },
+            (Some("engine_overloaded"), _, _) | (_, "server_error", _) | (_, _, 500..) => {
+                Error::ServerError {
+                    status: error.status,
+                    error,
+                }
+            }
+            _ => Error::BackendError {
+                message: error.message,
+                error_type: error.error_type,
+                code: error.code,
+                status: Some(error.status),
+                request_id: error.request_id,
+            },
+        }
+    }
+
+    /// Details of the error returned by the API, if this error was returned by the API and classified
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            Error::Authentication(error)
+            | Error::PermissionDenied(error)
+            | Error::NotFound(error)
+            | Error::ContextLengthExceeded(error)
+            | Error::ContentFiltered(error)
+            | Error::RateLimited { error, .. }
+            | Error::ServerError { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    /// HTTP status code of the response that caused this error, if any
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::BackendError { status, .. } => *status,
+            Error::ClientError(err) => err.status().map(|status| status.as_u16()),
+            _ => self.api_error().map(|error| error.status),
+        }
+    }
+
+    /// Unique ID of the request that caused this error, if provided by the API
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Error::BackendError { request_id, .. } => request_id.as_deref(),
+            _ => self
+                .api_error()
+                .and_then(|error| error.request_id.as_deref()),
+        }
+    }
+
+    /// Machine-readable error code returned by the API, if any
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::BackendError { code, .. } => code.as_deref(),
+            _ => self.api_error().and_then(|error| error.code.as_deref()),
+        }
+    }
+
+    /// Checks whether the request may succeed if retried later.
+    ///
+    /// This is the case for timeouts, connection failures, server errors and rate limits, except for an exhausted quota.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Timeout | Error::ServerError { .. } => true,
+            Error::RateLimited { error, .. } => {
+                error.code.as_deref() != Some("insufficient_quota")
+                    && error.error_type != "insufficient_quota"
+            }
+            Error::ClientError(err) => err.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::err::{ApiError, Error};
+
+    fn api_error(status: u16, error_type: &str, code: Option<&str>) -> ApiError {
+        ApiError {
+            message: "message".to_string(),
+            error_type: error_type.to_string(),
+            code: code.map(str::to_owned),
+            status,
+            request_id: Some("req_123".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_error_classification() {
+        let auth = Error::from_api(
+            api_error(401, "invalid_request_error", Some("invalid_api_key")),
+            None,
+        );
+        assert!(matches!(auth, Error::Authentication(_)));
+        assert!(!auth.is_retryable());
+        assert_eq!(auth.status(), Some(401));
+        assert_eq!(auth.request_id(), Some("req_123"));
+
+        let context = Error::from_api(
+            api_error(
+                400,
+                "invalid_request_error",
+                Some("context_length_exceeded"),
+            ),
+            None,
+        );
+        assert!(matches!(context, Error::ContextLengthExceeded(_)));
+
+        let rate_limited = Error::from_api(
+            api_error(429, "requests", Some("rate_limit_exceeded")),
+            Some(Duration::from_secs(2)),
+        );
+        assert!(matches!(
+            rate_limited,
+            Error::RateLimited {
+                retry_after: Some(_),
+                ..
+            }
+        ));
+        assert!(rate_limited.is_retryable());
+        let quota = Error::from_api(
+            api_error(429, "insufficient_quota", Some("insufficient_quota")),
+            None,
+        );
+        assert!(!quota.is_retryable());
+
+        let server = Error::from_api(api_error(503, "server_error", None), None);
+        assert!(matches!(server, Error::ServerError { status: 503, .. }));
+        assert!(server.is_retryable());
+
+        let other = Error::from_api(api_error(400, "invalid_request_error", None), None);
+        assert!(matches!(other, Error::BackendError { .. }));
+        assert_eq!(other.code(), None);
+        assert_eq!(other.status(), Some(400));
+    }
+}
@@ -0,0 +1,311 @@
+use std::path::Path;
+
+use base64::Engine;
+use derive_builder::Builder;
+use reqwest::multipart::Form;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::client::{decode_response, error_for_status, ChatGPT};
+use crate::err::Error;
+use crate::types::UploadFile;
+
+/// Size of generated images
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ImageSize {
+    /// 256x256 pixels, only supported by `dall-e-2`
+    #[serde(rename = "256x256")]
+    Size256x256,
+    /// 512x512 pixels, only supported by `dall-e-2`
+    #[serde(rename = "512x512")]
+    Size512x512,
+    /// 1024x1024 pixels
+    #[serde(rename = "1024x1024")]
+    Size1024x1024,
+    /// 1792x1024 pixels, only supported by `dall-e-3`
+    #[serde(rename = "1792x1024")]
+    Size1792x1024,
+    /// 1024x1792 pixels, only supported by `dall-e-3`
+    #[serde(rename = "1024x1792")]
+    Size1024x1792,
+}
+
+impl ImageSize {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImageSize::Size256x256 => "256x256",
+            ImageSize::Size512x512 => "512x512",
+            ImageSize::Size1024x1024 => "1024x1024",
+            ImageSize::Size1792x1024 => "1792x1024",
+            ImageSize::Size1024x1792 => "1024x1792",
+        }
+    }
+}
+
+/// Quality of generated images, only supported by `dall-e-3`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageQuality {
+    /// Standard quality
+    Standard,
+    /// Finer details and greater consistency
+    Hd,
+}
+
+/// Style of generated images, only supported by `dall-e-3`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStyle {
+    /// Hyper-real and dramatic images
+    Vivid,
+    /// More natural, less hyper-real images
+    Natural,
+}
+
+/// Format in which the generated images are returned
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageResponseFormat {
+    /// Images are returned as URLs, which are valid for an hour
+    #[default]
+    Url,
+    /// Images are returned as base64 encoded data
+    B64Json,
+}
+
+impl ImageResponseFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImageResponseFormat::Url => "url",
+            ImageResponseFormat::B64Json => "b64_json",
+        }
+    }
+}
+
+/// Options for generating, editing and creating variations of images.
+///
+/// Unset options are left to the API defaults. Quality and style only apply to generated images.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Builder)]
+#[builder(default, setter(into, strip_option))]
+pub struct ImageOptions {
+    /// The image model, e.g. `dall-e-3`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Amount of images to generate
+    #[serde(rename = "n", skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+    /// Size of the generated images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<ImageSize>,
+    /// Quality of the generated images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<ImageQuality>,
+    /// Style of the generated images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<ImageStyle>,
+    /// Format in which the generated images are returned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ImageResponseFormat>,
+    /// Identifier of the end user, to help detecting abuse
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl ImageOptions {
+    /// Adds the options supported by edits and variations to the multipart form
+    fn apply_to(&self, mut form: Form) -> Form {
+        if let Some(model) = &self.model {
+            form = form.text("model", model.clone());
+        }
+        if let Some(count) = self.count {
+            form = form.text("n", count.to_string());
+        }
+        if let Some(size) = self.size {
+            form = form.text("size", size.as_str());
+        }
+        if let Some(response_format) = self.response_format {
+            form = form.text("response_format", response_format.as_str());
+        }
+        if let Some(user) = &self.user {
+            form = form.text("user", user.clone());
+        }
+        form
+    }
+}
+
+/// A single generated image
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct GeneratedImage {
+    /// URL of the image, if requested with [`ImageResponseFormat::Url`]
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Base64 encoded image, if requested with [`ImageResponseFormat::B64Json`]
+    #[serde(default)]
+    pub b64_json: Option<String>,
+    /// The prompt the image was actually generated from, if it was revised
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+}
+
+impl GeneratedImage {
+    /// Decodes the base64 encoded image. Returns `None` if the image was returned as URL.
+    pub fn decode(&self) -> Option<crate::Result<Vec<u8>>> {
+        self.b64_json.as_ref().map(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|err| Error::ParsingError(err.to_string()))
+        })
+    }
+}
+
+/// Images returned from the images API
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct ImageResponse {
+    /// Unix seconds timestamp of when the images were created
+    #[serde(rename = "created")]
+    pub created_timestamp: u64,
+    /// The generated images
+    pub data: Vec<GeneratedImage>,
+}
+
+#[derive(Serialize)]
+struct ImageGenerationRequest<'a> {
+    prompt: &'a str,
+    #[serde(flatten)]
+    options: &'a ImageOptions,
+}
+
+impl ChatGPT {
+    /// Generates images from the prompt using the `/v1/images/generations` endpoint
+    pub async fn generate_image<S: AsRef<str>>(
+        &self,
+        prompt: S,
+        options: &ImageOptions,
+    ) -> crate::Result<ImageResponse> {
+        let response = self
+            .request(Method::POST, self.endpoint("images/generations")?)
+            .json(&ImageGenerationRequest {
+                prompt: prompt.as_ref(),
+                options,
+            })
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+
+    /// Edits the image according to the prompt using the `/v1/images/edits` endpoint.
+    ///
+    /// Transparent areas of the mask (or of the image itself, if no mask is provided) indicate where the image is edited.
+    pub async fn edit_image<S: Into<String>>(
+        &self,
+        image: UploadFile,
+        mask: Option<UploadFile>,
+        prompt: S,
+        options: &ImageOptions,
+    ) -> crate::Result<ImageResponse> {
+        let mut form = Form::new()
+            .part("image", image.into_part()?)
+            .text("prompt", prompt.into());
+        if let Some(mask) = mask {
+            form = form.part("mask", mask.into_part()?);
+        }
+        self.send_image_form("images/edits", options.apply_to(form))
+            .await
+    }
+
+    /// Creates variations of the image using the `/v1/images/variations` endpoint
+    pub async fn create_image_variation(
+        &self,
+        image: UploadFile,
+        options: &ImageOptions,
+    ) -> crate::Result<ImageResponse> {
+        let form = Form::new().part("image", image.into_part()?);
+        self.send_image_form("images/variations", options.apply_to(form))
+            .await
+    }
+
+    /// Returns the contents of the generated image, decoding it or downloading it from its URL
+    pub async fn image_bytes(&self, image: &GeneratedImage) -> crate::Result<Vec<u8>> {
+        if let Some(decoded) = image.decode() {
+            return decoded;
+        }
+        let url = image
+            .url
+            .as_deref()
+            .ok_or_else(|| Error::ParsingError("Image has neither data nor URL".to_string()))?;
+        // Not sent with `request()`, as the API key must not be sent to the image host
+        let response = self
+            .client
+            .get(url)
+            .timeout(self.config.timeout)
+            .send()
+            .await?;
+        Ok(error_for_status(response).await?.bytes().await?.to_vec())
+    }
+
+    /// Saves the generated image to a file, decoding it or downloading it from its URL
+    pub async fn save_image<P: AsRef<Path>>(
+        &self,
+        image: &GeneratedImage,
+        path: P,
+    ) -> crate::Result<()> {
+        let bytes = self.image_bytes(image).await?;
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+
+    async fn send_image_form(&self, endpoint: &str, form: Form) -> crate::Result<ImageResponse> {
+        let response = self
+            .request(Method::POST, self.endpoint(endpoint)?)
+            .multipart(form)
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::images::{
+        ImageGenerationRequest, ImageOptionsBuilder, ImageQuality, ImageResponse, ImageSize,
+    };
+
+    #[test]
+    fn test_generation_request() {
+        let options = ImageOptionsBuilder::default()
+            .model("dall-e-3")
+            .size(ImageSize::Size1792x1024)
+            .quality(ImageQuality::Hd)
+            .build()
+            .unwrap();
+        let request = serde_json::to_value(ImageGenerationRequest {
+            prompt: "A crab",
+            options: &options,
+        })
+        .unwrap();
+        assert_eq!(
+            request,
+            serde_json::json!({
+                "prompt": "A crab",
+                "model": "dall-e-3",
+                "size": "1792x1024",
+                "quality": "hd"
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let response: ImageResponse = serde_json::from_str(
+            r#"{ "created": 1700000000, "data": [{ "b64_json": "iVBORw==" }, { "url": "https://example.com/image.png" }] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response.data[0].decode().unwrap().unwrap(),
+            vec![0x89, b'P', b'N', b'G']
+        );
+        assert!(response.data[1].decode().is_none());
+    }
+}
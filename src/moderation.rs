@@ -0,0 +1,263 @@
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "streams")]
+use {
+    crate::types::{AbortReason, ResponseChunk},
+    futures_util::{Future, Stream, StreamExt},
+};
+
+use crate::client::{decode_response, ChatGPT};
+
+/// Flags or scores for each moderation category, returned from the `/v1/moderations` endpoint.
+///
+/// Categories not returned by the used moderation model are left at their default value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModerationCategories<T> {
+    /// Content expressing, inciting, or promoting hate based on a protected attribute
+    pub hate: T,
+    /// Hateful content that also includes violence or serious harm towards the targeted group
+    #[serde(rename = "hate/threatening")]
+    pub hate_threatening: T,
+    /// Content expressing, inciting, or promoting harassing language towards any target
+    pub harassment: T,
+    /// Harassment content that also includes violence or serious harm towards any target
+    #[serde(rename = "harassment/threatening")]
+    pub harassment_threatening: T,
+    /// Content that promotes, encourages, or depicts acts of self-harm
+    #[serde(rename = "self-harm")]
+    pub self_harm: T,
+    /// Content where the speaker expresses that they are engaging or intend to engage in acts of self-harm
+    #[serde(rename = "self-harm/intent")]
+    pub self_harm_intent: T,
+    /// Content that encourages performing acts of self-harm, or gives instructions on how to commit them
+    #[serde(rename = "self-harm/instructions")]
+    pub self_harm_instructions: T,
+    /// Content meant to arouse sexual excitement
+    pub sexual: T,
+    /// Sexual content that includes an individual who is under 18 years old
+    #[serde(rename = "sexual/minors")]
+    pub sexual_minors: T,
+    /// Content that depicts death, violence, or physical injury
+    pub violence: T,
+    /// Content that depicts death, violence, or physical injury in graphic detail
+    #[serde(rename = "violence/graphic")]
+    pub violence_graphic: T,
+    /// Content that includes instructions or advice that facilitate wrongdoing
+    pub illicit: T,
+    /// Illicit content that also includes references to violence or procuring a weapon
+    #[serde(rename = "illicit/violent")]
+    pub illicit_violent: T,
+}
+
+impl<T: Copy> ModerationCategories<T> {
+    /// Returns the API names of all categories along with their values
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, T)> {
+        [
+            ("hate", self.hate),
+            ("hate/threatening", self.hate_threatening),
+            ("harassment", self.harassment),
+            ("harassment/threatening", self.harassment_threatening),
+            ("self-harm", self.self_harm),
+            ("self-harm/intent", self.self_harm_intent),
+            ("self-harm/instructions", self.self_harm_instructions),
+            ("sexual", self.sexual),
+            ("sexual/minors", self.sexual_minors),
+            ("violence", self.violence),
+            ("violence/graphic", self.violence_graphic),
+            ("illicit", self.illicit),
+            ("illicit/violent", self.illicit_violent),
+        ]
+        .into_iter()
+    }
+}
+
+/// Moderation result for a single input
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct ModerationResult {
+    /// Whether the input violates any of the categories
+    pub flagged: bool,
+    /// Whether the input violates each of the categories
+    pub categories: ModerationCategories<bool>,
+    /// Confidence scores for each of the categories, between 0 and 1
+    pub category_scores: ModerationCategories<f64>,
+}
+
+impl ModerationResult {
+    /// Returns the API names of the violated categories, e.g. `hate/threatening`
+    pub fn flagged_categories(&self) -> Vec<&'static str> {
+        self.categories
+            .iter()
+            .filter(|(_, flagged)| *flagged)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+/// A response from the `/v1/moderations` endpoint
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct ModerationResponse {
+    /// Unique ID of the moderation request
+    pub id: String,
+    /// The model used for the moderation
+    pub model: String,
+    /// Results for each of the inputs, in the same order
+    pub results: Vec<ModerationResult>,
+}
+
+/// Controls which messages of a [`Conversation`](crate::converse::Conversation) are moderated before they are added to the history
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ModerationPolicy {
+    /// Also moderate the replies of the assistant.
+    ///
+    /// Streamed replies are moderated once they are complete, as their content has already been streamed by then.
+    /// Streams with a flagged reply end with an `Aborted` chunk instead of closing it.
+    pub moderate_replies: bool,
+    /// The moderation model to use, e.g. `omni-moderation-latest`. The API default is used if not set.
+    pub model: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ModerationRequest<'a> {
+    input: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+}
+
+impl ChatGPT {
+    /// Checks whether the input violates the usage policies using the `/v1/moderations` endpoint
+    pub async fn moderate<S: Into<String>>(&self, input: S) -> crate::Result<ModerationResult> {
+        let response = self.moderate_many(&[input.into()], None).await?;
+        response.results.into_iter().next().ok_or_else(|| {
+            crate::err::Error::ParsingError(
+                "Moderation response did not contain any results".to_string(),
+            )
+        })
+    }
+
+    /// Moderates multiple inputs in a single request, optionally with a specific moderation model
+    pub async fn moderate_many(
+        &self,
+        inputs: &[String],
+        model: Option<&str>,
+    ) -> crate::Result<ModerationResponse> {
+        let response = self
+            .request(Method::POST, self.endpoint("moderations")?)
+            .json(&ModerationRequest {
+                input: inputs,
+                model,
+            })
+            .send()
+            .await?;
+        decode_response(response).await
+    }
+}
+
+/// Moderates each streamed reply once it is complete, ending the stream with [`AbortReason::Flagged`]
+/// instead of closing a flagged reply
+#[cfg(feature = "streams")]
+pub(crate) fn moderate_stream<S, F, Fut>(
+    stream: S,
+    is_flagged: F,
+) -> impl Stream<Item = ResponseChunk> + Send + 'static
+where
+    S: Stream<Item = ResponseChunk> + Send + 'static,
+    F: FnMut(String) -> Fut + Send + 'static,
+    Fut: Future<Output = bool> + Send,
+{
+    let state = (Box::pin(stream), is_flagged, Vec::<String>::new());
+    futures_util::stream::unfold(Some(state), |state| async move {
+        let (mut stream, mut is_flagged, mut replies) = state?;
+        let chunk = stream.next().await?;
+        match &chunk {
+            ResponseChunk::Content {
+                delta,
+                response_index,
+            } => {
+                if replies.len() <= *response_index {
+                    replies.resize(response_index + 1, String::new());
+                }
+                replies[*response_index].push_str(delta);
+            }
+            ResponseChunk::CloseResponse { response_index } => {
+                let reply = replies
+                    .get_mut(*response_index)
+                    .map(std::mem::take)
+                    .unwrap_or_default();
+                if is_flagged(reply).await {
+                    let aborted = ResponseChunk::Aborted {
+                        reason: AbortReason::Flagged,
+                    };
+                    return Some((aborted, None));
+                }
+            }
+            _ => {}
+        }
+        Some((chunk, Some((stream, is_flagged, replies))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::moderation::ModerationResponse;
+
+    #[test]
+    fn test_moderation_response() {
+        let response: ModerationResponse = serde_json::from_str(
+            r#"{
+                "id": "modr-123",
+                "model": "text-moderation-007",
+                "results": [{
+                    "flagged": true,
+                    "categories": { "hate": false, "violence": true, "violence/graphic": true },
+                    "category_scores": { "hate": 0.01, "violence": 0.97, "violence/graphic": 0.6 }
+                }]
+            }"#,
+        )
+        .unwrap();
+        let result = &response.results[0];
+        assert!(result.flagged);
+        assert_eq!(
+            result.flagged_categories(),
+            vec!["violence", "violence/graphic"]
+        );
+        assert_eq!(result.category_scores.violence, 0.97);
+        assert_eq!(result.category_scores.sexual, 0.0);
+    }
+
+    #[cfg(feature = "streams")]
+    #[tokio::test]
+    async fn test_moderate_stream() {
+        use futures::StreamExt;
+
+        use crate::moderation::moderate_stream;
+        use crate::types::{AbortReason, ResponseChunk};
+
+        let content = |delta: &str, response_index| ResponseChunk::Content {
+            delta: delta.to_string(),
+            response_index,
+        };
+        let chunks = vec![
+            content("Fine", 0),
+            content("Very ", 1),
+            ResponseChunk::CloseResponse { response_index: 0 },
+            content("bad", 1),
+            ResponseChunk::CloseResponse { response_index: 1 },
+            ResponseChunk::Done,
+        ];
+        let moderated: Vec<ResponseChunk> = moderate_stream(
+            futures::stream::iter(chunks.clone()),
+            |reply: String| async move { reply == "Very bad" },
+        )
+        .collect()
+        .await;
+        // The flagged reply is not closed, and the stream ends
+        assert_eq!(moderated[..4], chunks[..4]);
+        assert_eq!(
+            moderated[4..],
+            [ResponseChunk::Aborted {
+                reason: AbortReason::Flagged
+            }]
+        );
+    }
+}
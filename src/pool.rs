@@ -0,0 +1,304 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Response;
+use tokio::time::Instant;
+
+use crate::client::ChatGPT;
+use crate::config::RequestOptions;
+use crate::err::Error;
+use crate::types::{CompletionRequest, ResponseMetadata};
+
+type PooledResponse<'a> =
+    Pin<Box<dyn Future<Output = crate::Result<(Response, ResponseMetadata)>> + Send + 'a>>;
+
+/// Strategy used by a [`ClientPool`] to pick the client for the next request
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BalancingStrategy {
+    /// Clients are picked in turns
+    #[default]
+    RoundRobin,
+    /// The client with the fewest requests in flight is picked
+    LeastInFlight,
+    /// The client with the most remaining requests and tokens, as reported by the rate limit headers, is picked.
+    /// Clients that did not report their limits yet are preferred.
+    RemainingQuota,
+}
+
+/// A pool of clients, e.g. with different API keys or endpoints, that completion requests are distributed between.
+///
+/// Clients returning authentication or server errors, failing to connect or exceeding their own timeout are temporarily ejected from the pool,
+/// and the request is retried with another client. Streamed requests are only counted as in flight until the response starts.
+///
+/// The pool is used through the [`ChatGPT`] client returned by [`ClientPool::into_client()`], so it can be used anywhere,
+/// including [`Conversation`](crate::converse::Conversation)s. The sampling parameters, engine and fallback engines are taken from
+/// that client's configuration, which is copied from the first client in the pool, while the endpoint, credentials and
+/// rate limits of each pooled client are used for its requests. Other endpoints, like model listing, use the first client.
+#[derive(Debug)]
+pub struct ClientPool {
+    members: Vec<PoolMember>,
+    strategy: BalancingStrategy,
+    ejection_duration: Duration,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct PoolMember {
+    client: ChatGPT,
+    in_flight: AtomicUsize,
+    health: Mutex<MemberHealth>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct MemberHealth {
+    ejected_until: Option<Instant>,
+    remaining_requests: Option<u32>,
+    remaining_tokens: Option<u32>,
+}
+
+/// Decrements the in-flight counter of a member once the request completes or is dropped
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ClientPool {
+    /// Constructs a new pool from the provided clients, using the round-robin strategy.
+    ///
+    /// Returns [`Error::BuilderError`] if no clients were provided.
+    pub fn new(clients: Vec<ChatGPT>) -> crate::Result<Self> {
+        if clients.is_empty() {
+            return Err(Error::BuilderError(
+                "a client pool requires at least one client".to_string(),
+            ));
+        }
+        Ok(Self {
+            members: clients
+                .into_iter()
+                .map(|client| PoolMember {
+                    client,
+                    in_flight: AtomicUsize::new(0),
+                    health: Mutex::new(MemberHealth::default()),
+                })
+                .collect(),
+            strategy: BalancingStrategy::default(),
+            ejection_duration: Duration::from_secs(30),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Sets the strategy used to pick the client for each request
+    pub fn strategy(mut self, strategy: BalancingStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets for how long unhealthy clients are ejected from the pool. Defaults to 30 seconds.
+    pub fn ejection_duration(mut self, duration: Duration) -> Self {
+        self.ejection_duration = duration;
+        self
+    }
+
+    /// Turns this pool into a client, which distributes its completion requests between the pooled clients
+    pub fn into_client(self) -> ChatGPT {
+        let front = self.members[0].client.clone();
+        front.with_pool(Arc::new(self))
+    }
+
+    /// Sends the completion request with one of the pooled clients
+    pub(crate) fn send_completion_request<'a>(
+        &'a self,
+        request: &'a CompletionRequest<'a>,
+        options: &'a RequestOptions,
+    ) -> PooledResponse<'a> {
+        Box::pin(async move {
+            let mut tried = vec![false; self.members.len()];
+            loop {
+                let index = self.pick(&tried, Instant::now());
+                tried[index] = true;
+                let has_alternatives = tried.iter().any(|tried| !tried);
+                let member = &self.members[index];
+
+                member.in_flight.fetch_add(1, Ordering::SeqCst);
+                let result = {
+                    let _guard = InFlightGuard(&member.in_flight);
+                    member
+                        .client
+                        .send_completion_request(request, options)
+                        .await
+                };
+
+                if let Ok((_, metadata)) = &result {
+                    self.record_quota(member, metadata);
+                }
+                if !is_unhealthy(&result, options) {
+                    return result;
+                }
+                member.health.lock().unwrap().ejected_until =
+                    Some(Instant::now() + self.ejection_duration);
+                if !has_alternatives {
+                    return result;
+                }
+            }
+        })
+    }
+
+    fn record_quota(&self, member: &PoolMember, metadata: &ResponseMetadata) {
+        let mut health = member.health.lock().unwrap();
+        if metadata.requests.remaining.is_some() {
+            health.remaining_requests = metadata.requests.remaining;
+        }
+        if metadata.tokens.remaining.is_some() {
+            health.remaining_tokens = metadata.tokens.remaining;
+        }
+    }
+
+    /// Picks the index of the client for the next attempt, skipping already tried clients.
+    ///
+    /// Ejected clients are only picked if all untried clients are ejected, in which case the one returning the soonest is picked.
+    fn pick(&self, tried: &[bool], now: Instant) -> usize {
+        let health: Vec<MemberHealth> = self
+            .members
+            .iter()
+            .map(|member| member.health.lock().unwrap().clone())
+            .collect();
+        let untried = (0..self.members.len()).filter(|index| !tried[*index]);
+        let healthy: Vec<usize> = untried
+            .clone()
+            .filter(|index| {
+                health[*index]
+                    .ejected_until
+                    .map_or(true, |until| until <= now)
+            })
+            .collect();
+        if healthy.is_empty() {
+            return untried
+                .min_by_key(|index| health[*index].ejected_until)
+                .unwrap_or(0);
+        }
+
+        match self.strategy {
+            BalancingStrategy::RoundRobin => {
+                let turn = self.next.fetch_add(1, Ordering::SeqCst);
+                healthy[turn % healthy.len()]
+            }
+            BalancingStrategy::LeastInFlight => healthy
+                .into_iter()
+                .min_by_key(|index| self.members[*index].in_flight.load(Ordering::SeqCst))
+                .unwrap(),
+            BalancingStrategy::RemainingQuota => healthy
+                .into_iter()
+                .max_by_key(|index| {
+                    let health = &health[*index];
+                    (
+                        health.remaining_requests.unwrap_or(u32::MAX),
+                        health.remaining_tokens.unwrap_or(u32::MAX),
+                    )
+                })
+                .unwrap(),
+        }
+    }
+}
+
+/// Whether the result shows a problem with the pooled client itself, so it should be ejected.
+///
+/// Timeouts only count when they come from the timeout of the client, not from the deadline of the call.
+fn is_unhealthy(
+    result: &crate::Result<(Response, ResponseMetadata)>,
+    options: &RequestOptions,
+) -> bool {
+    match result {
+        Ok((_, metadata)) => {
+            metadata.status == 401 || metadata.status == 403 || metadata.status >= 500
+        }
+        Err(Error::ClientError(err)) => err.is_connect(),
+        Err(Error::Timeout) => options.deadline.is_none(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use crate::client::ChatGPT;
+    use crate::config::RequestOptions;
+    use crate::err::Error;
+    use crate::pool::{is_unhealthy, BalancingStrategy, ClientPool};
+    use crate::types::ResponseMetadata;
+
+    fn pool(size: usize, strategy: BalancingStrategy) -> ClientPool {
+        let clients = (0..size)
+            .map(|index| ChatGPT::new(format!("key-{index}")).unwrap())
+            .collect();
+        ClientPool::new(clients).unwrap().strategy(strategy)
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let pool = pool(3, BalancingStrategy::RoundRobin);
+        let now = Instant::now();
+        let picks: Vec<usize> = (0..4).map(|_| pool.pick(&[false; 3], now)).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+        // Already tried clients are skipped
+        assert_eq!(pool.pick(&[true, true, false], now), 2);
+    }
+
+    #[test]
+    fn test_least_in_flight() {
+        let pool = pool(3, BalancingStrategy::LeastInFlight);
+        pool.members[0].in_flight.store(2, Ordering::SeqCst);
+        pool.members[1].in_flight.store(1, Ordering::SeqCst);
+        pool.members[2].in_flight.store(3, Ordering::SeqCst);
+        assert_eq!(pool.pick(&[false; 3], Instant::now()), 1);
+    }
+
+    #[test]
+    fn test_remaining_quota() {
+        let pool = pool(2, BalancingStrategy::RemainingQuota);
+        let mut metadata = ResponseMetadata::default();
+        metadata.requests.remaining = Some(10);
+        pool.record_quota(&pool.members[0], &metadata);
+        metadata.requests.remaining = Some(100);
+        pool.record_quota(&pool.members[1], &metadata);
+        assert_eq!(pool.pick(&[false; 2], Instant::now()), 1);
+    }
+
+    #[test]
+    fn test_ejection() {
+        let pool = pool(2, BalancingStrategy::RoundRobin);
+        let now = Instant::now();
+        pool.members[0].health.lock().unwrap().ejected_until = Some(now + Duration::from_secs(10));
+        pool.members[1].health.lock().unwrap().ejected_until = Some(now + Duration::from_secs(5));
+        // All clients are ejected, the one returning the soonest is picked
+        assert_eq!(pool.pick(&[false; 2], now), 1);
+        pool.members[1].health.lock().unwrap().ejected_until = None;
+        assert_eq!(pool.pick(&[false; 2], now), 1);
+        assert_eq!(pool.pick(&[false; 2], now), 1);
+        // Ejected clients return after the ejection duration
+        let later = now + Duration::from_secs(10);
+        assert_eq!(pool.pick(&[false, true], later), 0);
+    }
+
+    #[test]
+    fn test_unhealthy() {
+        let options = RequestOptions::default();
+        assert!(is_unhealthy(&Err(Error::Timeout), &options));
+        // The deadline of the call expiring is not the fault of the client
+        let options = RequestOptions {
+            deadline: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert!(!is_unhealthy(&Err(Error::Timeout), &options));
+        assert!(!is_unhealthy(&Err(Error::Cancelled), &options));
+    }
+}
@@ -1,10 +1,44 @@
-pub use crate::client::ChatGPT;
-pub use crate::config::{ChatGPTEngine, ModelConfiguration, ModelConfigurationBuilder};
+pub use crate::audio::{
+    SpeechOptions, SpeechOptionsBuilder, Transcription, TranscriptionFormat, TranscriptionOptions,
+    TranscriptionOptionsBuilder, Voice,
+};
+#[cfg(feature = "json")]
+pub use crate::batch::{Batch, BatchResults, BatchStatus, FilePurpose};
+pub use crate::branch::{HistoryTree, TreeNode};
+#[cfg(feature = "streams")]
+pub use crate::bulk::{BulkOptions, BulkProgress};
+#[cfg(feature = "json")]
+pub use crate::cache::{CacheBackend, CacheKey, DiskCache, MemoryCache, ResponseCache};
+pub use crate::client::{ChatGPT, ChatGPTBuilder};
+pub use crate::config::{
+    CancellationToken, ChatGPTEngine, EngineInfo, FallbackTrigger, ModelConfiguration,
+    ModelConfigurationBuilder, RequestOptions, RequestOptionsBuilder,
+};
 pub use crate::converse::Conversation;
+#[cfg(feature = "streams")]
+pub use crate::converse::ConversationStream;
+pub use crate::embeddings::{
+    Embedding, EmbeddingEncoding, EmbeddingOptions, EmbeddingOptionsBuilder, EmbeddingResponse,
+};
 #[cfg(feature = "functions")]
 pub use crate::functions::{gpt_function, FunctionValidationStrategy};
+pub use crate::images::{
+    GeneratedImage, ImageOptions, ImageOptionsBuilder, ImageQuality, ImageResponseFormat,
+    ImageSize, ImageStyle,
+};
+pub use crate::moderation::{ModerationPolicy, ModerationResult};
+pub use crate::pool::{BalancingStrategy, ClientPool};
+pub use crate::ratelimit::RateLimit;
+pub use crate::retrieval::{Embedder, InMemoryIndex, Passage, RetrievalPolicy, Retriever};
+pub use crate::snapshot::ConversationSnapshot;
+#[cfg(feature = "sqlite")]
+pub use crate::store::SqliteStore;
+#[cfg(feature = "json")]
+pub use crate::store::{ConversationStore, FileStore};
 #[cfg(feature = "streams")]
-pub use crate::types::ResponseChunk;
-pub use crate::types::{ChatMessage, MessageChoice, TokenUsage};
+pub use crate::types::{split_choices, ChoiceStream, ResponseChunk};
+pub use crate::types::{
+    ChatMessage, MessageChoice, MessageMetadata, Model, ResponseMetadata, TokenUsage, UploadFile,
+};
 pub use crate::Result;
 pub use url::Url;
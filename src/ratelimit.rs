@@ -0,0 +1,254 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::types::ResponseMetadata;
+
+/// Client-side rate limits, enforced before each completion request is sent.
+///
+/// Budgets are replenished continuously over the span of a minute, and adapt to the
+/// `x-ratelimit-remaining-*` and `x-ratelimit-reset-*` headers returned by the API.
+/// Token usage of a request is estimated with [`CompletionRequest::estimated_tokens()`](crate::types::CompletionRequest::estimated_tokens).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RateLimit {
+    /// Maximum amount of requests sent per minute
+    pub requests_per_minute: Option<u32>,
+    /// Maximum amount of tokens consumed per minute
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl RateLimit {
+    /// Constructs new rate limits with the provided request and token budgets
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute: Some(requests_per_minute),
+            tokens_per_minute: Some(tokens_per_minute),
+        }
+    }
+}
+
+/// A budget that is continuously replenished over a minute
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    updated: Instant,
+    blocked_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        Self {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+            updated: now,
+            blocked_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+        if self.blocked_until.is_some_and(|until| until <= now) {
+            self.blocked_until = None;
+        }
+    }
+
+    /// Returns how long to wait until the cost can be afforded
+    fn wait_time(&self, cost: f64, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.blocked_until {
+            return Some(until - now);
+        }
+        // Costs larger than the whole budget would otherwise never be afforded
+        let missing = cost.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(missing * 60.0 / self.capacity))
+        }
+    }
+
+    fn adapt(&mut self, remaining: Option<f64>, reset: Option<Duration>, now: Instant) {
+        if let Some(remaining) = remaining {
+            self.available = self.available.min(remaining);
+            if remaining < 1.0 {
+                if let Some(reset) = reset {
+                    self.blocked_until = Some(now + reset);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+impl LimiterState {
+    /// Attempts to spend the budget for a single request, returning the time to wait otherwise
+    fn try_acquire(&mut self, tokens: u32, now: Instant) -> Option<Duration> {
+        let mut wait = Duration::ZERO;
+        for (bucket, cost) in [(&mut self.requests, 1.0), (&mut self.tokens, tokens as f64)] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                if let Some(bucket_wait) = bucket.wait_time(cost, now) {
+                    wait = wait.max(bucket_wait);
+                }
+            }
+        }
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+        if let Some(bucket) = &mut self.requests {
+            bucket.available -= 1.0;
+        }
+        if let Some(bucket) = &mut self.tokens {
+            bucket.available -= (tokens as f64).min(bucket.capacity);
+        }
+        None
+    }
+}
+
+/// Enforces [`RateLimit`]s, shared between all clones of a client
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// Callers wait on this lock in FIFO order, so they are served fairly
+    queue: tokio::sync::Mutex<()>,
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        let now = Instant::now();
+        Self {
+            queue: tokio::sync::Mutex::new(()),
+            state: Mutex::new(LimiterState {
+                requests: limit.requests_per_minute.map(|rpm| Bucket::new(rpm, now)),
+                tokens: limit.tokens_per_minute.map(|tpm| Bucket::new(tpm, now)),
+            }),
+        }
+    }
+
+    /// Waits until the budget for a request with the provided estimated token usage is available, and spends it
+    pub async fn acquire(&self, tokens: u32) {
+        let _turn = self.queue.lock().await;
+        loop {
+            let wait = self
+                .state
+                .lock()
+                .unwrap()
+                .try_acquire(tokens, Instant::now());
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Adapts the remaining budgets to the rate limits reported by the API
+    pub fn observe(&self, metadata: &ResponseMetadata) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        for (bucket, status) in [
+            (&mut state.requests, &metadata.requests),
+            (&mut state.tokens, &metadata.tokens),
+        ] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                bucket.adapt(status.remaining.map(f64::from), status.reset, now);
+            }
+        }
+    }
+}
+
+/// Parses rate limit reset durations in the format used by the API, e.g. `20ms`, `1s` or `6m0.5s`
+pub fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * multiplier;
+        rest = &rest[unit_end..];
+    }
+    Duration::try_from_secs_f64(total).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use crate::ratelimit::{parse_reset_duration, Bucket, LimiterState};
+
+    #[test]
+    fn test_reset_duration_parsing() {
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(parse_reset_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(
+            parse_reset_duration("1h2m0.5s"),
+            Some(Duration::from_millis(3_720_500))
+        );
+        assert_eq!(parse_reset_duration("soon"), None);
+        assert_eq!(parse_reset_duration(""), None);
+    }
+
+    #[test]
+    fn test_budget_exhaustion() {
+        let now = Instant::now();
+        let mut state = LimiterState {
+            requests: Some(Bucket::new(2, now)),
+            tokens: Some(Bucket::new(600, now)),
+        };
+        assert_eq!(state.try_acquire(100, now), None);
+        assert_eq!(state.try_acquire(100, now), None);
+        // Out of requests, one request is replenished every 30 seconds
+        assert_eq!(state.try_acquire(100, now), Some(Duration::from_secs(30)));
+        let later = now + Duration::from_secs(30);
+        assert_eq!(state.try_acquire(100, later), None);
+        // 300 tokens remaining, 10 tokens are replenished each second
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(state.try_acquire(500, much_later), None);
+        assert_eq!(
+            state.try_acquire(200, much_later),
+            Some(Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn test_server_reported_exhaustion() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(100, now);
+        bucket.adapt(Some(0.0), Some(Duration::from_secs(5)), now);
+        assert_eq!(bucket.wait_time(1.0, now), Some(Duration::from_secs(5)));
+        let later = now + Duration::from_secs(5);
+        bucket.refill(later);
+        assert_eq!(bucket.wait_time(1.0, later), None);
+    }
+}
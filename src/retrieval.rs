@@ -0,0 +1,326 @@
+use std::fmt::{self, Debug, Write};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+use crate::client::ChatGPT;
+use crate::embeddings::cosine_similarity;
+use crate::err::Error;
+use crate::types::{ChatMessage, MessageMetadata, Role};
+
+/// A passage of a document, returned from a [`Retriever`]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Passage {
+    /// Where the passage comes from, e.g. a file name or URL. Used as the citation.
+    pub source: String,
+    /// The text of the passage
+    pub content: String,
+    /// Relevance of the passage to the query, higher is more relevant
+    pub score: f32,
+}
+
+/// A document store that can be queried for passages relevant to a message
+#[async_trait]
+pub trait Retriever: Send + Sync {
+    /// Returns up to `limit` passages relevant to the query, most relevant first
+    async fn retrieve(&self, query: &str, limit: usize) -> crate::Result<Vec<Passage>>;
+}
+
+/// Turns texts into embedding vectors, used by the [`InMemoryIndex`].
+///
+/// Implemented for the [`ChatGPT`] client using the default embedding options,
+/// and for closures mapping a text to a vector, e.g. a mock embedding function for offline use.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Creates an embedding vector for each of the inputs, in the same order
+    async fn embed(&self, inputs: &[String]) -> crate::Result<Vec<Vec<f32>>>;
+}
+
+#[async_trait]
+impl Embedder for ChatGPT {
+    async fn embed(&self, inputs: &[String]) -> crate::Result<Vec<Vec<f32>>> {
+        let response = ChatGPT::embed(self, inputs.iter().cloned()).await?;
+        Ok(response
+            .data
+            .into_iter()
+            .map(|embedding| embedding.vector)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl<F> Embedder for F
+where
+    F: Fn(&str) -> Vec<f32> + Send + Sync,
+{
+    async fn embed(&self, inputs: &[String]) -> crate::Result<Vec<Vec<f32>>> {
+        Ok(inputs.iter().map(|input| self(input)).collect())
+    }
+}
+
+/// A simple in-memory vector index, ranking documents by the cosine similarity of their embeddings to the query
+pub struct InMemoryIndex<E: Embedder> {
+    embedder: E,
+    documents: RwLock<Vec<IndexedDocument>>,
+}
+
+struct IndexedDocument {
+    source: String,
+    content: String,
+    vector: Vec<f32>,
+}
+
+impl<E: Embedder> InMemoryIndex<E> {
+    /// Constructs a new empty index using the embedder
+    pub fn new(embedder: E) -> Self {
+        Self {
+            embedder,
+            documents: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Embeds and adds the documents, given as `(source, content)` pairs
+    pub async fn add_documents<I, S, C>(&self, documents: I) -> crate::Result<()>
+    where
+        I: IntoIterator<Item = (S, C)>,
+        S: Into<String>,
+        C: Into<String>,
+    {
+        let (sources, contents): (Vec<String>, Vec<String>) = documents
+            .into_iter()
+            .map(|(source, content)| (source.into(), content.into()))
+            .unzip();
+        let vectors = self.embed(&contents).await?;
+        self.documents
+            .write()
+            .await
+            .extend(sources.into_iter().zip(contents).zip(vectors).map(
+                |((source, content), vector)| IndexedDocument {
+                    source,
+                    content,
+                    vector,
+                },
+            ));
+        Ok(())
+    }
+
+    /// Embeds the inputs, failing if the embedder does not return exactly one vector for each input
+    async fn embed(&self, inputs: &[String]) -> crate::Result<Vec<Vec<f32>>> {
+        let vectors = self.embedder.embed(inputs).await?;
+        if vectors.len() != inputs.len() {
+            return Err(Error::EmbeddingCountMismatch {
+                expected: inputs.len(),
+                actual: vectors.len(),
+            });
+        }
+        Ok(vectors)
+    }
+
+    /// Returns the amount of indexed documents
+    pub async fn len(&self) -> usize {
+        self.documents.read().await.len()
+    }
+
+    /// Checks whether no documents were indexed yet
+    pub async fn is_empty(&self) -> bool {
+        self.documents.read().await.is_empty()
+    }
+}
+
+#[async_trait]
+impl<E: Embedder> Retriever for InMemoryIndex<E> {
+    async fn retrieve(&self, query: &str, limit: usize) -> crate::Result<Vec<Passage>> {
+        let query = self.embed(&[query.to_string()]).await?.remove(0);
+        let documents = self.documents.read().await;
+        let mut passages: Vec<Passage> = documents
+            .iter()
+            .map(|document| Passage {
+                source: document.source.clone(),
+                content: document.content.clone(),
+                score: cosine_similarity(&query, &document.vector),
+            })
+            .collect();
+        passages.sort_by(|a, b| b.score.total_cmp(&a.score));
+        passages.truncate(limit);
+        Ok(passages)
+    }
+}
+
+/// Makes a [`Conversation`](crate::converse::Conversation) query a [`Retriever`] before each user message,
+/// adding the relevant passages to the history as a system message with numbered citations
+#[derive(Clone)]
+pub struct RetrievalPolicy {
+    /// The document store to query
+    pub retriever: Arc<dyn Retriever>,
+    /// Maximum amount of passages added for each message
+    pub limit: usize,
+    /// Passages scoring below this value are left out
+    pub min_score: f32,
+}
+
+impl RetrievalPolicy {
+    /// Constructs a new policy, adding up to 3 passages to each user message
+    pub fn new<R: Retriever + 'static>(retriever: R) -> Self {
+        Self {
+            retriever: Arc::new(retriever),
+            limit: 3,
+            min_score: f32::MIN,
+        }
+    }
+
+    /// Sets the maximum amount of passages added for each message
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets the minimum score of the added passages
+    pub fn min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+
+    /// Retrieves the passages for the message and builds the context message, if any relevant passages were found
+    pub(crate) async fn context_message(&self, query: &str) -> crate::Result<Option<ChatMessage>> {
+        let passages: Vec<Passage> = self
+            .retriever
+            .retrieve(query, self.limit)
+            .await?
+            .into_iter()
+            .filter(|passage| passage.score >= self.min_score)
+            .collect();
+        if passages.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            ChatMessage::new(Role::System, format_context(&passages)).with_metadata(
+                MessageMetadata {
+                    retrieval_context: true,
+                    ..MessageMetadata::now()
+                },
+            ),
+        ))
+    }
+}
+
+impl Debug for RetrievalPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetrievalPolicy")
+            .field("limit", &self.limit)
+            .field("min_score", &self.min_score)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The beginning of each context message
+const CONTEXT_PREAMBLE: &str = "Use the following passages to answer the next message. \
+    Cite the passages you use by their number in square brackets, e.g. [1].\n";
+
+/// Checks whether the message is a context message, built by a [`RetrievalPolicy`]
+pub(crate) fn is_context_message(message: &ChatMessage) -> bool {
+    message
+        .metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.retrieval_context)
+}
+
+/// Formats the passages as a numbered list, with instructions to cite them
+fn format_context(passages: &[Passage]) -> String {
+    let mut context = String::from(CONTEXT_PREAMBLE);
+    for (number, passage) in passages.iter().enumerate() {
+        let _ = write!(
+            context,
+            "\n[{}] (source: {})\n{}\n",
+            number + 1,
+            passage.source,
+            passage.content
+        );
+    }
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::err::Error;
+    use crate::retrieval::{
+        is_context_message, Embedder, InMemoryIndex, RetrievalPolicy, Retriever,
+    };
+    use crate::types::{ChatMessage, Role};
+
+    /// Counts the occurrences of a few keywords, good enough to tell the test documents apart
+    fn mock_embedding(text: &str) -> Vec<f32> {
+        let text = text.to_lowercase();
+        ["rust", "crab", "python", "snake"]
+            .iter()
+            .map(|keyword| text.matches(keyword).count() as f32)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_index() {
+        let index = InMemoryIndex::new(mock_embedding);
+        index
+            .add_documents([
+                ("rust.md", "Rust has a crab mascot called Ferris"),
+                (
+                    "python.md",
+                    "Python is named after a comedy group, not the snake",
+                ),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(index.len().await, 2);
+
+        let passages = index
+            .retrieve("Tell me about the Rust crab", 1)
+            .await
+            .unwrap();
+        assert_eq!(passages.len(), 1);
+        assert_eq!(passages[0].source, "rust.md");
+
+        // Documents are not dropped silently if the embedder returns too few vectors
+        struct Truncating;
+        #[async_trait::async_trait]
+        impl Embedder for Truncating {
+            async fn embed(&self, inputs: &[String]) -> crate::Result<Vec<Vec<f32>>> {
+                Ok(vec![vec![1.0]; inputs.len() - 1])
+            }
+        }
+        let truncating = InMemoryIndex::new(Truncating);
+        assert!(matches!(
+            truncating
+                .add_documents([("a.md", "A"), ("b.md", "B")])
+                .await,
+            Err(Error::EmbeddingCountMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        assert!(truncating.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_context_message() {
+        let index = InMemoryIndex::new(mock_embedding);
+        index
+            .add_documents([
+                ("rust.md", "Rust has a crab mascot"),
+                ("snake.md", "Snakes"),
+            ])
+            .await
+            .unwrap();
+        let policy = RetrievalPolicy::new(index).min_score(0.5);
+
+        let message = policy.context_message("Rust").await.unwrap().unwrap();
+        assert_eq!(message.role, Role::System);
+        assert!(message.content.contains("[1] (source: rust.md)"));
+        assert!(!message.content.contains("snake.md"));
+        assert_eq!(policy.context_message("Java").await.unwrap(), None);
+
+        // Context messages are recognized by their metadata, not by their content
+        assert!(is_context_message(&message));
+        let copy = ChatMessage::new(Role::System, message.content.clone());
+        assert!(!is_context_message(&copy));
+    }
+}
@@ -0,0 +1,252 @@
+#[cfg(feature = "json")]
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::branch::HistoryTree;
+use crate::client::ChatGPT;
+use crate::config::ModelConfiguration;
+use crate::converse::Conversation;
+use crate::err::Error;
+use crate::types::ChatMessage;
+
+/// The current version of the [`ConversationSnapshot`] format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The full state of a [`Conversation`], that can be persisted and restored later.
+///
+/// Unlike the plain history, the snapshot also captures the model configuration and the functions the conversation relies on.
+/// Snapshots carry a format version, and snapshots of unsupported versions are rejected when they are read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationSnapshot {
+    /// Version of the snapshot format, see [`SNAPSHOT_VERSION`]
+    pub version: u32,
+    /// All messages of the conversation including their metadata, starting with the system message
+    pub messages: Vec<ChatMessage>,
+    /// The model configuration the conversation used, if set.
+    ///
+    /// Only the engine, sampling parameters, token limit, penalties and reply count are restored,
+    /// the API endpoint, timeouts and fallbacks of the restoring client are kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<ModelConfiguration>,
+    /// Whether all functions are sent with each message
+    #[serde(default)]
+    pub always_send_functions: bool,
+    /// Names of the functions, that have to be registered on the conversation it is restored to
+    #[serde(default)]
+    pub functions: Vec<String>,
+    /// All branches of the conversation, if it was ever forked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<HistoryTree>,
+}
+
+impl ConversationSnapshot {
+    /// Parses a snapshot from JSON, checking its format version.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub fn from_json(data: &str) -> crate::Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(data)?;
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| {
+                Error::ParsingError("Conversation snapshot has no version".to_string())
+            })?;
+        let version = u32::try_from(version).unwrap_or(u32::MAX);
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::UnsupportedSnapshotVersion(version));
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Serializes the snapshot to JSON.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> crate::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Reads a snapshot from a local JSON file.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub async fn load<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        Self::from_json(&tokio::fs::read_to_string(path).await?)
+    }
+
+    /// Saves the snapshot to a local JSON file, replacing it atomically.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        crate::converse::write_atomic(path.as_ref(), self.to_json()?.as_bytes()).await
+    }
+}
+
+impl Conversation {
+    /// Captures the full state of the conversation
+    pub fn snapshot(&self) -> ConversationSnapshot {
+        ConversationSnapshot {
+            version: SNAPSHOT_VERSION,
+            messages: self.history.clone(),
+            config: Some(self.client.config.clone()),
+            #[cfg(feature = "functions")]
+            always_send_functions: self.always_send_functions,
+            #[cfg(not(feature = "functions"))]
+            always_send_functions: false,
+            functions: self.function_names(),
+            tree: (!self.tree.is_empty()).then(|| self.tree().into_owned()),
+        }
+    }
+
+    /// Restores the state of the snapshot into this conversation, replacing its history.
+    ///
+    /// Register the functions the snapshot requires with [`Self::add_function()`] beforehand,
+    /// otherwise [`Error::MissingFunctions`] is returned and the conversation is left unchanged.
+    pub fn restore_snapshot(&mut self, snapshot: ConversationSnapshot) -> crate::Result<()> {
+        let registered = self.function_names();
+        let missing: Vec<String> = snapshot
+            .functions
+            .into_iter()
+            .filter(|name| !registered.contains(name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::MissingFunctions(missing));
+        }
+
+        if let Some(config) = snapshot.config {
+            // Only the conversation-level settings are restored, the endpoint, timeouts and fallbacks stay with the client
+            let current = &mut self.client.config;
+            current.engine = config.engine;
+            current.temperature = config.temperature;
+            current.top_p = config.top_p;
+            current.max_tokens = config.max_tokens;
+            current.presence_penalty = config.presence_penalty;
+            current.frequency_penalty = config.frequency_penalty;
+            current.reply_count = config.reply_count;
+        }
+        #[cfg(feature = "functions")]
+        {
+            self.always_send_functions = snapshot.always_send_functions;
+        }
+        self.history = snapshot.messages;
+        self.tree = snapshot.tree.unwrap_or_default();
+        Ok(())
+    }
+
+    /// Saves the full state of the conversation to a local JSON file, see [`ConversationSnapshot`].
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub async fn save_snapshot<P: AsRef<Path>>(&self, to: P) -> crate::Result<()> {
+        self.snapshot().save(to).await
+    }
+
+    /// Sorted names of the registered functions
+    fn function_names(&self) -> Vec<String> {
+        #[cfg(feature = "functions")]
+        {
+            let mut names: Vec<String> = self.functions.keys().cloned().collect();
+            names.sort();
+            names
+        }
+        #[cfg(not(feature = "functions"))]
+        Vec::new()
+    }
+}
+
+impl ChatGPT {
+    /// Restores a conversation from a local snapshot file, saved with [`Conversation::save_snapshot()`].
+    ///
+    /// Fails with [`Error::MissingFunctions`] if the snapshot requires functions. Restore such snapshots with
+    /// [`Conversation::restore_snapshot()`] after registering the functions instead.
+    ///
+    /// Requires the `json` crate feature
+    #[cfg(feature = "json")]
+    pub async fn restore_conversation_snapshot<P: AsRef<Path>>(
+        &self,
+        file: P,
+    ) -> crate::Result<Conversation> {
+        let snapshot = ConversationSnapshot::load(file).await?;
+        let mut conversation = Conversation::new_with_history(self.clone(), Vec::new());
+        conversation.restore_snapshot(snapshot)?;
+        Ok(conversation)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use crate::client::ChatGPT;
+    use crate::err::Error;
+    use crate::snapshot::{ConversationSnapshot, SNAPSHOT_VERSION};
+    use crate::types::{ChatMessage, Role};
+
+    #[tokio::test]
+    async fn test_snapshot_roundtrip() {
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation.client.config.temperature = 0.0;
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello"));
+
+        let snapshot = conversation.snapshot();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert!(snapshot.messages[0].metadata.is_some());
+
+        let path = std::env::temp_dir().join(format!("chatgpt-snapshot-{}", std::process::id()));
+        snapshot.save(&path).await.unwrap();
+        let restored = client.restore_conversation_snapshot(&path).await.unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(restored.history, conversation.history);
+        assert_eq!(restored.client.config.temperature, 0.0);
+        assert_eq!(restored.snapshot(), snapshot);
+
+        // The endpoint of the restoring client is kept
+        let mut foreign = snapshot.clone();
+        foreign.config.as_mut().unwrap().api_url =
+            "https://example.com/v1/chat/completions".parse().unwrap();
+        let mut other = client.new_conversation();
+        other.restore_snapshot(foreign).unwrap();
+        assert_eq!(other.client.config.temperature, 0.0);
+        assert_eq!(
+            other.client.endpoint("models").unwrap(),
+            client.endpoint("models").unwrap()
+        );
+
+        // Branches are persisted with the snapshot
+        let mut conversation = restored;
+        conversation.fork_at(0).unwrap();
+        let snapshot = conversation.snapshot();
+        assert_eq!(snapshot.tree.as_ref().unwrap().len(), 2);
+        let mut restored = client.new_conversation();
+        restored.restore_snapshot(snapshot).unwrap();
+        assert_eq!(restored.history.len(), 1);
+        assert_eq!(restored.alternatives(0).unwrap().len(), 1);
+        restored.switch_branch(1).unwrap();
+        assert_eq!(restored.history.len(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_validation() {
+        let client = ChatGPT::new("key").unwrap();
+        let mut snapshot = client.new_conversation().snapshot();
+        snapshot.functions = vec!["get_weather".to_string()];
+
+        let mut conversation = client.new_conversation_directed("Other");
+        assert!(matches!(
+            conversation.restore_snapshot(snapshot.clone()),
+            Err(Error::MissingFunctions(missing)) if missing == ["get_weather"]
+        ));
+        assert_eq!(conversation.history[0].content, "Other");
+
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            ConversationSnapshot::from_json(&snapshot.to_json().unwrap()),
+            Err(Error::UnsupportedSnapshotVersion(_))
+        ));
+        assert!(ConversationSnapshot::from_json("{\"messages\": []}").is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use crate::converse::write_atomic;
+use crate::err::Error;
+use crate::types::ChatMessage;
+
+/// Persistent storage of conversation histories, keyed by conversation ID
+#[async_trait]
+pub trait ConversationStore: Send + Sync {
+    /// Loads the history of the conversation, or `None` if it does not exist
+    async fn load(&self, id: &str) -> crate::Result<Option<Vec<ChatMessage>>>;
+    /// Saves the whole history of the conversation, replacing any previously stored history
+    async fn save(&self, id: &str, history: &[ChatMessage]) -> crate::Result<()>;
+    /// Appends a single message to the history of the conversation, creating it if it does not exist
+    async fn append(&self, id: &str, message: &ChatMessage) -> crate::Result<()>;
+    /// Lists the IDs of all stored conversations
+    async fn list(&self) -> crate::Result<Vec<String>>;
+    /// Deletes the conversation. Deleting a conversation that does not exist is not an error.
+    async fn delete(&self, id: &str) -> crate::Result<()>;
+}
+
+/// A store keeping each conversation in a JSON Lines file in a directory, with one message per line
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    /// Constructs a new store in the directory, which is created if it does not exist
+    pub fn new<P: Into<PathBuf>>(directory: P) -> crate::Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, id: &str) -> crate::Result<PathBuf> {
+        let valid = !id.is_empty()
+            && !id.starts_with('.')
+            && !id.contains(['/', '\\', '\0'])
+            && !id.contains(std::path::MAIN_SEPARATOR);
+        if !valid {
+            return Err(Error::ParsingError(format!(
+                "Invalid conversation ID: {id:?}"
+            )));
+        }
+        Ok(self.directory.join(format!("{id}.jsonl")))
+    }
+}
+
+/// Serializes the messages as JSON Lines
+pub(crate) fn to_jsonl(history: &[ChatMessage]) -> crate::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for message in history {
+        serde_json::to_writer(&mut data, message)?;
+        data.push(b'\n');
+    }
+    Ok(data)
+}
+
+/// Parses messages stored as JSON Lines, returning whether the last line was truncated and ignored.
+///
+/// Only an unterminated last line, as left by an interrupted append, is ignored, other invalid lines fail the parsing.
+pub(crate) fn parse_jsonl(data: &str) -> crate::Result<(Vec<ChatMessage>, bool)> {
+    let mut messages = Vec::new();
+    let mut lines = data.lines().peekable();
+    while let Some(line) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(message) => messages.push(message),
+            Err(_) if lines.peek().is_none() && !data.ends_with('\n') => {
+                return Ok((messages, true))
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok((messages, false))
+}
+
+/// Reads messages stored as JSON Lines, rewriting the file without the truncated last line if there is one,
+/// so that further appends start on a new line
+async fn read_jsonl(path: &Path) -> crate::Result<Option<Vec<ChatMessage>>> {
+    let data = match tokio::fs::read_to_string(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let (messages, truncated) = parse_jsonl(&data)?;
+    if truncated {
+        write_atomic(path, &to_jsonl(&messages)?).await?;
+    }
+    Ok(Some(messages))
+}
+
+/// Appends a single message to a JSON Lines file
+async fn append_jsonl(path: &Path, message: &ChatMessage) -> crate::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&to_jsonl(std::slice::from_ref(message))?)
+        .await?;
+    file.flush().await?;
+    Ok(())
+}
+
+/// A single JSON Lines file, used as the journal of a [`Conversation`](crate::converse::Conversation).
+/// Conversation IDs are ignored, as the journal holds a single conversation.
+pub(crate) struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl ConversationStore for Journal {
+    async fn load(&self, _id: &str) -> crate::Result<Option<Vec<ChatMessage>>> {
+        read_jsonl(&self.path).await
+    }
+
+    async fn save(&self, _id: &str, history: &[ChatMessage]) -> crate::Result<()> {
+        write_atomic(&self.path, &to_jsonl(history)?).await
+    }
+
+    async fn append(&self, _id: &str, message: &ChatMessage) -> crate::Result<()> {
+        append_jsonl(&self.path, message).await
+    }
+
+    async fn list(&self) -> crate::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    async fn delete(&self, _id: &str) -> crate::Result<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl ConversationStore for FileStore {
+    async fn load(&self, id: &str) -> crate::Result<Option<Vec<ChatMessage>>> {
+        read_jsonl(&self.path(id)?).await
+    }
+
+    async fn save(&self, id: &str, history: &[ChatMessage]) -> crate::Result<()> {
+        write_atomic(&self.path(id)?, &to_jsonl(history)?).await
+    }
+
+    async fn append(&self, id: &str, message: &ChatMessage) -> crate::Result<()> {
+        append_jsonl(&self.path(id)?, message).await
+    }
+
+    async fn list(&self) -> crate::Result<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        let mut ids = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "jsonl")
+            {
+                if let Some(id) = path.file_stem() {
+                    ids.push(id.to_string_lossy().into_owned());
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn delete(&self, id: &str) -> crate::Result<()> {
+        match tokio::fs::remove_file(self.path(id)?).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A store keeping all conversations in an embedded SQLite database
+///
+/// Requires the `sqlite` crate feature
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    connection: std::sync::Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Opens or creates the database at the path
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        Self::from_connection(rusqlite::Connection::open(path)?)
+    }
+
+    /// Creates a new database, which only exists in memory
+    pub fn in_memory() -> crate::Result<Self> {
+        Self::from_connection(rusqlite::Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: rusqlite::Connection) -> crate::Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS messages (
+                conversation_id TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                message TEXT NOT NULL,
+                PRIMARY KEY (conversation_id, position)
+            );",
+        )?;
+        Ok(Self {
+            connection: std::sync::Arc::new(std::sync::Mutex::new(connection)),
+        })
+    }
+
+    /// Runs the operation on the connection in a blocking task
+    async fn run<T, F>(&self, operation: F) -> crate::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut rusqlite::Connection) -> crate::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || operation(&mut connection.lock().unwrap()))
+            .await
+            .map_err(|err| Error::IOError(std::io::Error::new(std::io::ErrorKind::Other, err)))?
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl ConversationStore for SqliteStore {
+    async fn load(&self, id: &str) -> crate::Result<Option<Vec<ChatMessage>>> {
+        let id = id.to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT message FROM messages WHERE conversation_id = ?1 ORDER BY position",
+            )?;
+            let rows = statement
+                .query_map([&id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+            rows.iter()
+                .map(|row| serde_json::from_str(row).map_err(Error::from))
+                .collect::<crate::Result<Vec<_>>>()
+                .map(Some)
+        })
+        .await
+    }
+
+    async fn save(&self, id: &str, history: &[ChatMessage]) -> crate::Result<()> {
+        let id = id.to_string();
+        let messages = history
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute("DELETE FROM messages WHERE conversation_id = ?1", [&id])?;
+            for (position, message) in messages.iter().enumerate() {
+                transaction.execute(
+                    "INSERT INTO messages (conversation_id, position, message) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![id, position as i64, message],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append(&self, id: &str, message: &ChatMessage) -> crate::Result<()> {
+        let id = id.to_string();
+        let message = serde_json::to_string(message)?;
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO messages (conversation_id, position, message)
+                SELECT ?1, COALESCE(MAX(position) + 1, 0), ?2 FROM messages WHERE conversation_id = ?1",
+                rusqlite::params![id, message],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self) -> crate::Result<Vec<String>> {
+        self.run(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT DISTINCT conversation_id FROM messages ORDER BY conversation_id",
+            )?;
+            let ids = statement
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> crate::Result<()> {
+        let id = id.to_string();
+        self.run(move |connection| {
+            connection.execute("DELETE FROM messages WHERE conversation_id = ?1", [&id])?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::{ConversationStore, FileStore};
+    use crate::types::{ChatMessage, Role};
+
+    async fn exercise(store: &dyn ConversationStore) {
+        let history = vec![
+            ChatMessage::new(Role::System, "You are a helpful assistant"),
+            ChatMessage::new(Role::User, "Hello"),
+        ];
+        assert_eq!(store.load("first").await.unwrap(), None);
+        store.save("first", &history).await.unwrap();
+        store
+            .append("first", &ChatMessage::new(Role::Assistant, "Hi"))
+            .await
+            .unwrap();
+        store
+            .append("second", &ChatMessage::new(Role::User, "Hey"))
+            .await
+            .unwrap();
+
+        let loaded = store.load("first").await.unwrap().unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded[2].content, "Hi");
+        assert_eq!(store.list().await.unwrap(), vec!["first", "second"]);
+
+        // Saving replaces the whole history
+        store.save("first", &history[..1]).await.unwrap();
+        assert_eq!(store.load("first").await.unwrap().unwrap().len(), 1);
+
+        store.delete("first").await.unwrap();
+        store.delete("first").await.unwrap();
+        assert_eq!(store.list().await.unwrap(), vec!["second"]);
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let directory = std::env::temp_dir().join(format!("chatgpt-store-{}", std::process::id()));
+        let store = FileStore::new(&directory).unwrap();
+        exercise(&store).await;
+        assert!(store.load("../escape").await.is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_conversation_sync() {
+        use std::sync::Arc;
+
+        use crate::client::ChatGPT;
+
+        let directory = std::env::temp_dir().join(format!("chatgpt-sync-{}", std::process::id()));
+        let store: Arc<dyn ConversationStore> = Arc::new(FileStore::new(&directory).unwrap());
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation
+            .attach_store(store.clone(), "chat")
+            .await
+            .unwrap();
+
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello"));
+        conversation
+            .history
+            .push(ChatMessage::new(Role::Assistant, "Hi"));
+        conversation.sync_store().await.unwrap();
+        let restored = client
+            .load_conversation(store.clone(), "chat")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.history, conversation.history);
+
+        conversation.rollback();
+        conversation.sync_store().await.unwrap();
+        assert_eq!(store.load("chat").await.unwrap().unwrap().len(), 1);
+
+        // Regrowing the history to the persisted length after a rollback still replaces the persisted messages
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hey"));
+        conversation
+            .history
+            .push(ChatMessage::new(Role::Assistant, "Hi"));
+        conversation.sync_store().await.unwrap();
+        conversation.rollback();
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello again"));
+        conversation
+            .history
+            .push(ChatMessage::new(Role::Assistant, "Hi again"));
+        conversation.sync_store().await.unwrap();
+        assert_eq!(
+            store.load("chat").await.unwrap().unwrap()[1].content,
+            "Hello again"
+        );
+        assert!(client
+            .load_conversation(store, "missing")
+            .await
+            .unwrap()
+            .is_none());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        use crate::client::ChatGPT;
+
+        let path = std::env::temp_dir().join(format!("chatgpt-journal-{}", std::process::id()));
+        let client = ChatGPT::new("key").unwrap();
+        let mut conversation = client.new_conversation();
+        conversation.save_history_journal(&path).await.unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, "Hello"));
+        conversation.sync_store().await.unwrap();
+
+        // Simulate a crash in the middle of appending the next message
+        let mut data = std::fs::read_to_string(&path).unwrap();
+        data.push_str("{\"role\":\"assistant\",\"cont");
+        std::fs::write(&path, data).unwrap();
+
+        let mut restored = client.restore_conversation_journal(&path).await.unwrap();
+        assert_eq!(restored.history, conversation.history);
+        restored
+            .history
+            .push(ChatMessage::new(Role::Assistant, "Hi"));
+        restored.sync_store().await.unwrap();
+        let restored = client.restore_conversation_journal(&path).await.unwrap();
+        assert_eq!(restored.history.len(), 3);
+        assert_eq!(restored.history[2].content, "Hi");
+
+        // Invalid lines other than the unterminated last one are still rejected
+        std::fs::write(&path, "{}\n{}\n").unwrap();
+        assert!(client.restore_conversation_journal(&path).await.is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let store = crate::store::SqliteStore::in_memory().unwrap();
+        exercise(&store).await;
+    }
+}
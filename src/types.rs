@@ -1,6 +1,25 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::ChatGPTEngine;
+use crate::err::Error;
 #[cfg(feature = "functions")]
 use crate::functions::FunctionCall;
-use serde::{Deserialize, Deserializer, Serialize};
+use crate::ratelimit::parse_reset_duration;
+use reqwest::header::HeaderMap;
+use reqwest::multipart::Part;
+use reqwest::StatusCode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "streams")]
+use {
+    futures::task::{waker, ArcWake},
+    futures::Stream,
+    std::collections::VecDeque,
+    std::pin::Pin,
+    std::sync::{Arc, Mutex, PoisonError},
+    std::task::{Context, Poll, Waker},
+};
 
 /// A role of a message sender, can be:
 /// - `System`, for starting system message, that sets the tone of model
@@ -31,15 +50,210 @@ pub struct ChatMessage {
     #[cfg(feature = "functions")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub function_call: Option<FunctionCall>,
+    /// Local metadata of the message, persisted with the history but never sent to the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+}
+
+/// Metadata of a message in the history, e.g. when and by which model it was generated
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageMetadata {
+    /// Locally generated unique ID of the message
+    pub id: Option<String>,
+    /// Unix seconds timestamp of when the message was created
+    pub created_at: Option<u64>,
+    /// The model that generated the message
+    pub model: Option<String>,
+    /// The reason the model stopped generating the message
+    pub finish_reason: Option<String>,
+    /// Token usage of the response the message was received in
+    pub usage: Option<TokenUsage>,
+    /// ID of the response the message was received in
+    pub response_id: Option<String>,
+    /// Whether the message is the context retrieved for the following user message by a [`RetrievalPolicy`](crate::retrieval::RetrievalPolicy)
+    pub retrieval_context: bool,
+}
+
+impl MessageMetadata {
+    /// Constructs the metadata of a message created now, with a new local ID
+    pub fn now() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            id: Some(format!(
+                "msg-{:x}-{:x}",
+                since.as_nanos(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            )),
+            created_at: Some(since.as_secs()),
+            ..Default::default()
+        }
+    }
+
+    /// Constructs the metadata of the message of the choice with the provided index in the response
+    pub fn from_response(response: &CompletionResponse, choice: usize) -> Self {
+        let now = Self::now();
+        Self {
+            created_at: response.created_timestamp.or(now.created_at),
+            model: Some(response.model.clone()),
+            finish_reason: response
+                .message_choices
+                .get(choice)
+                .map(|choice| choice.finish_reason.clone()),
+            usage: Some(response.usage.clone()),
+            response_id: response.message_id.clone(),
+            ..now
+        }
+    }
+}
+
+/// Prefix of postcard histories, followed by the format version.
+/// Histories without it were written before messages had metadata, using only the role and content.
+#[cfg(feature = "postcard")]
+const POSTCARD_MAGIC: &[u8] = b"\0chatgpt-rs";
+/// The current version of the postcard history format
+#[cfg(feature = "postcard")]
+const POSTCARD_VERSION: u8 = 1;
+
+/// Postcard layout of a [`ChatMessage`]. Postcard is not self-describing, so all fields are always written,
+/// regardless of the enabled crate features.
+#[cfg(feature = "postcard")]
+#[derive(Serialize, Deserialize)]
+struct PostcardMessage {
+    role: Role,
+    content: String,
+    /// Name and arguments of the function call
+    function_call: Option<(String, String)>,
+    metadata: Option<MessageMetadata>,
+}
+
+/// Postcard layout of a [`ChatMessage`] in histories without the version prefix
+#[cfg(feature = "postcard")]
+#[derive(Deserialize)]
+struct LegacyPostcardMessage {
+    role: Role,
+    content: String,
+}
+
+/// Serializes the messages to the versioned postcard history format
+#[cfg(feature = "postcard")]
+pub(crate) fn to_postcard(messages: &[ChatMessage]) -> crate::Result<Vec<u8>> {
+    let messages: Vec<PostcardMessage> = messages
+        .iter()
+        .map(|message| PostcardMessage {
+            role: message.role,
+            content: message.content.clone(),
+            #[cfg(feature = "functions")]
+            function_call: message
+                .function_call
+                .as_ref()
+                .map(|call| (call.name.clone(), call.arguments.clone())),
+            #[cfg(not(feature = "functions"))]
+            function_call: None,
+            metadata: message.metadata.clone(),
+        })
+        .collect();
+    let mut data = POSTCARD_MAGIC.to_vec();
+    data.push(POSTCARD_VERSION);
+    data.extend(postcard::to_allocvec(&messages)?);
+    Ok(data)
+}
+
+/// Deserializes messages from a postcard history, including histories written before the format was versioned
+#[cfg(feature = "postcard")]
+pub(crate) fn from_postcard(data: &[u8]) -> crate::Result<Vec<ChatMessage>> {
+    let Some(versioned) = data.strip_prefix(POSTCARD_MAGIC) else {
+        let messages: Vec<LegacyPostcardMessage> = postcard::from_bytes(data)?;
+        return Ok(messages
+            .into_iter()
+            .map(|message| ChatMessage::new(message.role, message.content))
+            .collect());
+    };
+    match versioned.split_first() {
+        Some((&POSTCARD_VERSION, data)) => {
+            let messages: Vec<PostcardMessage> = postcard::from_bytes(data)?;
+            Ok(messages
+                .into_iter()
+                .map(|message| {
+                    let mut result = ChatMessage::new(message.role, message.content);
+                    #[cfg(feature = "functions")]
+                    {
+                        result.function_call = message
+                            .function_call
+                            .map(|(name, arguments)| FunctionCall { name, arguments });
+                    }
+                    result.metadata = message.metadata;
+                    result
+                })
+                .collect())
+        }
+        other => Err(Error::ParsingError(format!(
+            "Unsupported postcard history version: {:?}",
+            other.map(|(version, _)| version)
+        ))),
+    }
+}
+
+/// The fields of a [`ChatMessage`] sent to the API, leaving out the local metadata
+#[derive(Serialize)]
+struct WireMessage<'a> {
+    role: Role,
+    content: &'a str,
+    #[cfg(feature = "functions")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: &'a Option<FunctionCall>,
+}
+
+impl<'a> From<&'a ChatMessage> for WireMessage<'a> {
+    fn from(message: &'a ChatMessage) -> Self {
+        Self {
+            role: message.role,
+            content: &message.content,
+            #[cfg(feature = "functions")]
+            function_call: &message.function_call,
+        }
+    }
+}
+
+fn serialize_wire_messages<S>(
+    messages: &&Vec<ChatMessage>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(messages.iter().map(WireMessage::from))
 }
 
 fn deserialize_maybe_null<'de, D>(deserializer: D) -> Result<String, D::Error>
-    where D: Deserializer<'de> {
+where
+    D: Deserializer<'de>,
+{
     let buf = Option::<String>::deserialize(deserializer)?;
     Ok(buf.unwrap_or(String::new()))
 }
 
 impl ChatMessage {
+    /// Constructs a new chat message with the provided role and content
+    pub fn new<S: Into<String>>(role: Role, content: S) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            #[cfg(feature = "functions")]
+            function_call: None,
+            metadata: None,
+        }
+    }
+
+    /// Sets the local metadata of the message
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
     /// Converts multiple response chunks into multiple (or a single) chat messages
     #[cfg(feature = "streams")]
     pub fn from_response_chunks(chunks: Vec<ResponseChunk>) -> Vec<Self> {
@@ -59,13 +273,7 @@ impl ChatMessage {
                     role,
                     response_index: _,
                 } => {
-                    let msg = ChatMessage {
-                        role,
-                        content: String::new(),
-                        #[cfg(feature = "functions")]
-                        function_call: None,
-                    };
-                    result.push(msg);
+                    result.push(ChatMessage::new(role, String::new()));
                 }
                 _ => {}
             }
@@ -79,7 +287,9 @@ impl ChatMessage {
 pub struct CompletionRequest<'a> {
     /// The model to be used, currently `gpt-3.5-turbo`, but may change in future
     pub model: &'a str,
-    /// The message history, including the message that requires completion, which should be the last one
+    /// The message history, including the message that requires completion, which should be the last one.
+    /// The metadata of the messages is not sent.
+    #[serde(serialize_with = "serialize_wire_messages")]
     pub messages: &'a Vec<ChatMessage>,
     /// Whether the message response should be gradually streamed
     pub stream: bool,
@@ -103,6 +313,24 @@ pub struct CompletionRequest<'a> {
     pub functions: &'a Vec<serde_json::Value>,
 }
 
+impl CompletionRequest<'_> {
+    /// Roughly estimates the amount of tokens this request might consume, which is the estimated size of the
+    /// prompt plus the `max_tokens` of the completion.
+    ///
+    /// The prompt size is estimated at around 4 characters per token, with a small overhead for each message.
+    pub fn estimated_tokens(&self) -> u32 {
+        let prompt: usize = self
+            .messages
+            .iter()
+            .map(|message| (message.content.chars().count() + 3) / 4 + 4)
+            .sum();
+        let completion = self.max_tokens.unwrap_or(0) * self.reply_count.max(1);
+        u32::try_from(prompt)
+            .unwrap_or(u32::MAX)
+            .saturating_add(completion)
+    }
+}
+
 /// Represents a response from the API
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
 #[serde(untagged)]
@@ -124,10 +352,13 @@ pub struct CompletionError {
     /// The type of error. Example: `server_error`
     #[serde(rename = "type")]
     pub error_type: String,
+    /// Machine-readable error code, if provided. Example: `context_length_exceeded`
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
 /// A response struct received from the API after requesting a message completion
-#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct CompletionResponse {
     /// Unique ID of the message, but not in a UUID format.
     /// Example: `chatcmpl-6p5FEv1JHictSSnDZsGU4KvbuBsbu`
@@ -143,6 +374,9 @@ pub struct CompletionResponse {
     /// Message choices for this response, guaranteed to contain at least one message response
     #[serde(rename = "choices")]
     pub message_choices: Vec<MessageChoice>,
+    /// Metadata from the HTTP response headers. Always set for responses returned by the client.
+    #[serde(skip)]
+    pub metadata: Option<ResponseMetadata>,
 }
 
 impl CompletionResponse {
@@ -153,8 +387,68 @@ impl CompletionResponse {
     }
 }
 
+/// Metadata extracted from the HTTP response of the API, useful for support requests and capacity planning
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ResponseMetadata {
+    /// HTTP status code of the response
+    pub status: u16,
+    /// Unique ID of the request, from the `x-request-id` header
+    pub request_id: Option<String>,
+    /// Time the API spent processing the request, from the `openai-processing-ms` header
+    pub processing_time: Option<Duration>,
+    /// Request rate limits, from the `x-ratelimit-*-requests` headers
+    pub requests: RateLimitStatus,
+    /// Token rate limits, from the `x-ratelimit-*-tokens` headers
+    pub tokens: RateLimitStatus,
+    /// How long to wait before retrying, from the `retry-after-ms` or `retry-after` headers
+    pub retry_after: Option<Duration>,
+    /// The engine the request was sent to, which differs from the configured engine if a fallback engine was used
+    pub engine: Option<ChatGPTEngine>,
+    /// Whether the response was served from the [`ResponseCache`](crate::cache::ResponseCache) instead of the API
+    pub cached: bool,
+}
+
+/// State of a single rate limit reported by the API
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct RateLimitStatus {
+    /// The maximum amount permitted before the limit is exhausted
+    pub limit: Option<u32>,
+    /// The amount remaining before the limit is exhausted
+    pub remaining: Option<u32>,
+    /// Time until the limit is reset to its initial state
+    pub reset: Option<Duration>,
+}
+
+impl ResponseMetadata {
+    /// Extracts the metadata from the status code and headers of a response
+    pub fn from_headers(status: StatusCode, headers: &HeaderMap) -> Self {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let number = |name: &str| header(name).and_then(|value| value.trim().parse::<u32>().ok());
+        let rate_limit = |kind: &str| RateLimitStatus {
+            limit: number(&format!("x-ratelimit-limit-{kind}")),
+            remaining: number(&format!("x-ratelimit-remaining-{kind}")),
+            reset: header(&format!("x-ratelimit-reset-{kind}")).and_then(parse_reset_duration),
+        };
+        Self {
+            status: status.as_u16(),
+            request_id: header("x-request-id").map(str::to_owned),
+            processing_time: number("openai-processing-ms")
+                .map(|millis| Duration::from_millis(millis as u64)),
+            requests: rate_limit("requests"),
+            tokens: rate_limit("tokens"),
+            retry_after: number("retry-after-ms")
+                .map(|millis| Duration::from_millis(millis as u64))
+                .or_else(|| number("retry-after").map(|secs| Duration::from_secs(secs as u64))),
+            engine: None,
+            cached: false,
+        }
+    }
+}
+
 /// A message completion choice struct
-#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct MessageChoice {
     /// The actual message
     pub message: ChatMessage,
@@ -165,7 +459,7 @@ pub struct MessageChoice {
 }
 
 /// The token usage of a specific response
-#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct TokenUsage {
     /// Tokens spent on the prompt message (including previous messages)
     pub prompt_tokens: u32,
@@ -175,6 +469,92 @@ pub struct TokenUsage {
     pub total_tokens: u32,
 }
 
+/// A model available through the API, returned from the `/v1/models` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Model {
+    /// The model ID, e.g. `gpt-4`
+    pub id: String,
+    /// Unix seconds timestamp of when the model was created
+    #[serde(rename = "created")]
+    pub created_timestamp: Option<u64>,
+    /// The organization that owns the model, e.g. `openai` or `system`
+    pub owned_by: Option<String>,
+}
+
+impl Model {
+    /// Maps this model onto a [`ChatGPTEngine`], which is [`ChatGPTEngine::Custom`] for models unknown to this library
+    pub fn engine(&self) -> ChatGPTEngine {
+        ChatGPTEngine::from(self.id.clone())
+    }
+}
+
+/// A list of models returned from the API
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ModelList {
+    /// The listed models
+    pub data: Vec<Model>,
+}
+
+/// A file uploaded to the API, e.g. audio for transcriptions or images for edits
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UploadFile {
+    /// Name of the file. The extension determines the file format, e.g. `speech.mp3`
+    pub name: String,
+    /// Contents of the file
+    pub data: Vec<u8>,
+}
+
+impl UploadFile {
+    /// Constructs a file from its name and contents
+    pub fn from_bytes<S: Into<String>, B: Into<Vec<u8>>>(name: S, data: B) -> Self {
+        Self {
+            name: name.into(),
+            data: data.into(),
+        }
+    }
+
+    /// Reads a file from disk
+    pub async fn from_path<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::ParsingError("File path has no file name".to_string()))?;
+        Ok(Self {
+            name,
+            data: tokio::fs::read(path).await?,
+        })
+    }
+
+    /// Guesses the MIME type of the file from its extension
+    pub fn mime_type(&self) -> &'static str {
+        let extension = self
+            .name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("webp") => "image/webp",
+            Some("mp3" | "mpga" | "mpeg") => "audio/mpeg",
+            Some("m4a" | "mp4") => "audio/mp4",
+            Some("wav") => "audio/wav",
+            Some("webm") => "audio/webm",
+            Some("ogg") => "audio/ogg",
+            Some("flac") => "audio/flac",
+            Some("json") => "application/json",
+            Some("jsonl") => "application/jsonl",
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// Turns the file into a multipart form part
+    pub(crate) fn into_part(self) -> crate::Result<Part> {
+        let mime = self.mime_type();
+        Ok(Part::bytes(self.data).file_name(self.name).mime_str(mime)?)
+    }
+}
+
 /// A single response chunk, returned from streamed request
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
 #[cfg(feature = "streams")]
@@ -198,10 +578,228 @@ pub enum ResponseChunk {
         /// Index of the message finished. Used when `reply_count` is set to more than 1 in API config
         response_index: usize,
     },
+    /// Metadata of the HTTP response, always sent as the first chunk of the stream
+    Metadata(ResponseMetadata),
+    /// The stream was aborted before completion. No more chunks are sent after this one.
+    Aborted {
+        /// The reason the stream was aborted
+        reason: AbortReason,
+    },
     /// Marks end of stream
     Done,
 }
 
+/// Reason a streamed response was aborted
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
+#[cfg(feature = "streams")]
+pub enum AbortReason {
+    /// The request was cancelled with a [`CancellationToken`](crate::config::CancellationToken)
+    Cancelled,
+    /// The deadline of the request has expired
+    DeadlineExceeded,
+    /// No chunk was received within the idle timeout
+    IdleTimeout,
+    /// The connection was closed or broke before the response was complete
+    ConnectionLost,
+    /// A chunk sent by the API could not be parsed
+    InvalidChunk,
+    /// A reply was flagged by the [`ModerationPolicy`](crate::moderation::ModerationPolicy) of the conversation,
+    /// or could not be moderated
+    Flagged,
+}
+
+/// A stream of the chunks of a single reply choice, split from a streamed response by [`split_choices`].
+///
+/// The chunks are re-indexed to a `response_index` of 0, so each stream looks like a response with a single choice,
+/// e.g. for [`ChatMessage::from_response_chunks`]. The original index is available with [`ChoiceStream::index()`].
+#[cfg(feature = "streams")]
+pub struct ChoiceStream<'a> {
+    index: usize,
+    demux: Arc<Mutex<ChoiceDemux<'a>>>,
+}
+
+#[cfg(feature = "streams")]
+struct ChoiceDemux<'a> {
+    source: Pin<Box<dyn Stream<Item = ResponseChunk> + Send + 'a>>,
+    queues: Vec<VecDeque<ResponseChunk>>,
+    dropped: Vec<bool>,
+    wakers: Arc<ChoiceWakers>,
+    source_waker: Waker,
+    finished: bool,
+}
+
+/// Wakers of all streams waiting for a chunk. The source is polled with a waker that wakes all of them,
+/// so no stream is left waiting if the one that polled the source last is dropped or stops polling.
+#[cfg(feature = "streams")]
+struct ChoiceWakers(Mutex<Vec<Option<Waker>>>);
+
+/// Splits a streamed response with multiple reply choices into a stream for each of the `count` choices.
+///
+/// The metadata and the final [`ResponseChunk::Done`] or [`ResponseChunk::Aborted`] chunks are sent to all streams.
+/// The streams can be consumed concurrently or one after another, as the chunks of the other choices are buffered meanwhile.
+/// Chunks of dropped streams are not buffered, neither are chunks of choices with an index of `count` or above.
+#[cfg(feature = "streams")]
+pub fn split_choices<'a, S>(stream: S, count: usize) -> Vec<ChoiceStream<'a>>
+where
+    S: Stream<Item = ResponseChunk> + Send + 'a,
+{
+    let wakers = Arc::new(ChoiceWakers(Mutex::new(vec![None; count])));
+    let demux = Arc::new(Mutex::new(ChoiceDemux {
+        source: Box::pin(stream),
+        queues: vec![VecDeque::new(); count],
+        dropped: vec![false; count],
+        source_waker: waker(wakers.clone()),
+        wakers,
+        finished: false,
+    }));
+    (0..count)
+        .map(|index| ChoiceStream {
+            index,
+            demux: demux.clone(),
+        })
+        .collect()
+}
+
+#[cfg(feature = "streams")]
+impl ChoiceStream<'_> {
+    /// Returns the index of the reply choice of this stream
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+#[cfg(feature = "streams")]
+impl Stream for ChoiceStream<'_> {
+    type Item = ResponseChunk;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut demux = self.demux.lock().unwrap_or_else(PoisonError::into_inner);
+        let demux = &mut *demux;
+        loop {
+            if let Some(chunk) = demux.queues[self.index].pop_front() {
+                return Poll::Ready(Some(chunk));
+            }
+            if demux.finished {
+                return Poll::Ready(None);
+            }
+            // Registered before polling, so a wake during the poll is not lost
+            demux.wakers.register(self.index, cx.waker());
+            let mut source_cx = Context::from_waker(&demux.source_waker);
+            match demux.source.as_mut().poll_next(&mut source_cx) {
+                Poll::Ready(Some(chunk)) => demux.route(chunk, self.index),
+                Poll::Ready(None) => {
+                    demux.finished = true;
+                    demux.wakers.wake_all();
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "streams")]
+impl Drop for ChoiceStream<'_> {
+    fn drop(&mut self) {
+        let mut demux = self.demux.lock().unwrap_or_else(PoisonError::into_inner);
+        demux.dropped[self.index] = true;
+        demux.queues[self.index].clear();
+        demux.wakers.unregister(self.index);
+    }
+}
+
+#[cfg(feature = "streams")]
+impl ChoiceDemux<'_> {
+    /// Queues the chunk for the stream of its choice, or for all streams if it does not belong to a choice
+    fn route(&mut self, chunk: ResponseChunk, current: usize) {
+        let (index, chunk) = match chunk {
+            ResponseChunk::Content {
+                delta,
+                response_index,
+            } => (
+                response_index,
+                ResponseChunk::Content {
+                    delta,
+                    response_index: 0,
+                },
+            ),
+            ResponseChunk::BeginResponse {
+                role,
+                response_index,
+            } => (
+                response_index,
+                ResponseChunk::BeginResponse {
+                    role,
+                    response_index: 0,
+                },
+            ),
+            ResponseChunk::CloseResponse { response_index } => (
+                response_index,
+                ResponseChunk::CloseResponse { response_index: 0 },
+            ),
+            other => {
+                for (queue, _) in self
+                    .queues
+                    .iter_mut()
+                    .zip(&self.dropped)
+                    .filter(|(_, dropped)| !**dropped)
+                {
+                    queue.push_back(other.clone());
+                }
+                self.wakers.wake_all();
+                return;
+            }
+        };
+        if index < self.queues.len() && !self.dropped[index] {
+            self.queues[index].push_back(chunk);
+            if index != current {
+                self.wakers.wake_choice(index);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "streams")]
+impl ChoiceWakers {
+    fn register(&self, index: usize, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match &mut wakers[index] {
+            Some(current) if current.will_wake(waker) => {}
+            slot => *slot = Some(waker.clone()),
+        }
+    }
+
+    fn unregister(&self, index: usize) {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)[index] = None;
+    }
+
+    fn wake_choice(&self, index: usize) {
+        let waker = self.0.lock().unwrap_or_else(PoisonError::into_inner)[index].take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn wake_all(&self) {
+        let wakers: Vec<Waker> = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter_mut()
+            .filter_map(Option::take)
+            .collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(feature = "streams")]
+impl ArcWake for ChoiceWakers {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.wake_all();
+    }
+}
+
 /// A part of a chunked inbound response
 #[derive(Debug, Clone, Deserialize)]
 #[cfg(feature = "streams")]
@@ -238,3 +836,210 @@ pub enum InboundChunkPayload {
     /// Closes a single message
     Close {},
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use reqwest::StatusCode;
+
+    use crate::types::ResponseMetadata;
+
+    #[test]
+    fn test_metadata_from_headers() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("x-request-id", "req_123"),
+            ("openai-processing-ms", "420"),
+            ("x-ratelimit-limit-requests", "500"),
+            ("x-ratelimit-remaining-requests", "499"),
+            ("x-ratelimit-reset-requests", "120ms"),
+            ("x-ratelimit-remaining-tokens", "29000"),
+            ("x-ratelimit-reset-tokens", "2s"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+        let metadata = ResponseMetadata::from_headers(StatusCode::OK, &headers);
+        assert_eq!(metadata.status, 200);
+        assert_eq!(metadata.request_id.as_deref(), Some("req_123"));
+        assert_eq!(metadata.processing_time, Some(Duration::from_millis(420)));
+        assert_eq!(metadata.requests.limit, Some(500));
+        assert_eq!(metadata.requests.remaining, Some(499));
+        assert_eq!(metadata.requests.reset, Some(Duration::from_millis(120)));
+        assert_eq!(metadata.tokens.limit, None);
+        assert_eq!(metadata.tokens.remaining, Some(29000));
+        assert_eq!(metadata.tokens.reset, Some(Duration::from_secs(2)));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_message_metadata_not_sent() {
+        use crate::client::ChatGPT;
+        use crate::types::{ChatMessage, MessageMetadata, Role};
+
+        let message = ChatMessage::new(Role::User, "Hello").with_metadata(MessageMetadata {
+            model: Some("gpt-4".to_string()),
+            ..MessageMetadata::now()
+        });
+        let persisted = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ChatMessage>(&persisted).unwrap(),
+            message
+        );
+
+        let history = vec![message];
+        let client = ChatGPT::new("key").unwrap();
+        let request = serde_json::to_value(client.completion_request(&history, false)).unwrap();
+        assert_eq!(
+            request["messages"],
+            serde_json::json!([{"role": "user", "content": "Hello"}])
+        );
+        assert_ne!(MessageMetadata::now().id, MessageMetadata::now().id);
+    }
+
+    #[cfg(feature = "streams")]
+    #[tokio::test]
+    async fn test_split_choices() {
+        use futures::StreamExt;
+
+        use crate::types::{split_choices, ChatMessage, ResponseChunk, Role};
+
+        let begin = |response_index| ResponseChunk::BeginResponse {
+            role: Role::Assistant,
+            response_index,
+        };
+        let content = |delta: &str, response_index| ResponseChunk::Content {
+            delta: delta.to_string(),
+            response_index,
+        };
+        let source = futures::stream::iter(vec![
+            begin(0),
+            begin(1),
+            content("Hello", 1),
+            content("Hi", 0),
+            content(" there", 1),
+            ResponseChunk::CloseResponse { response_index: 0 },
+            ResponseChunk::CloseResponse { response_index: 1 },
+            ResponseChunk::Done,
+        ]);
+
+        let mut streams = split_choices(source, 2);
+        let second = streams.pop().unwrap();
+        let first = streams.pop().unwrap();
+        assert_eq!(second.index(), 1);
+
+        // The first stream is consumed completely before the second one
+        let first: Vec<ResponseChunk> = first.collect().await;
+        let second: Vec<ResponseChunk> = second.collect().await;
+        assert_eq!(first.last(), Some(&ResponseChunk::Done));
+        assert_eq!(second.last(), Some(&ResponseChunk::Done));
+        assert_eq!(ChatMessage::from_response_chunks(first)[0].content, "Hi");
+        assert_eq!(
+            ChatMessage::from_response_chunks(second)[0].content,
+            "Hello there"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_choices_out_of_range() {
+        use futures::StreamExt;
+
+        use crate::types::{split_choices, ChatMessage, ResponseChunk, Role};
+
+        let source = futures::stream::iter(vec![
+            ResponseChunk::BeginResponse {
+                role: Role::Assistant,
+                response_index: 0,
+            },
+            ResponseChunk::Content {
+                delta: "Ignored".to_string(),
+                response_index: 1,
+            },
+            ResponseChunk::Content {
+                delta: "Hi".to_string(),
+                response_index: 0,
+            },
+            ResponseChunk::CloseResponse { response_index: 1 },
+            ResponseChunk::CloseResponse { response_index: 0 },
+            ResponseChunk::Done,
+        ]);
+
+        // Chunks of choices beyond the requested count are dropped
+        let only = split_choices(source, 1).pop().unwrap();
+        let chunks: Vec<ResponseChunk> = only.collect().await;
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(ChatMessage::from_response_chunks(chunks)[0].content, "Hi");
+    }
+
+    #[cfg(feature = "streams")]
+    #[tokio::test]
+    async fn test_split_choices_wakeup() {
+        use futures::StreamExt;
+
+        use crate::types::{split_choices, ResponseChunk};
+
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let mut streams = split_choices(receiver, 2);
+        let mut second = streams.pop().unwrap();
+        let first = streams.pop().unwrap();
+        let first = tokio::spawn(first.collect::<Vec<ResponseChunk>>());
+        tokio::task::yield_now().await;
+
+        // The second stream polls the source last and is dropped, the first one still has to be woken
+        assert!(futures::poll!(second.next()).is_pending());
+        drop(second);
+        sender
+            .unbounded_send(ResponseChunk::Content {
+                delta: "Hi".to_string(),
+                response_index: 1,
+            })
+            .unwrap();
+        sender.unbounded_send(ResponseChunk::Done).unwrap();
+        drop(sender);
+        let first = tokio::time::timeout(std::time::Duration::from_secs(5), first)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, [ResponseChunk::Done]);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard_history() {
+        use crate::types::{from_postcard, to_postcard, ChatMessage, MessageMetadata, Role};
+
+        // Written by a version without message metadata
+        let legacy = [
+            3, 0, 27, 89, 111, 117, 32, 97, 114, 101, 32, 97, 32, 104, 101, 108, 112, 102, 117,
+            108, 32, 97, 115, 115, 105, 115, 116, 97, 110, 116, 2, 5, 72, 101, 108, 108, 111, 1, 9,
+            72, 105, 32, 116, 104, 101, 114, 101, 33,
+        ];
+        let history = from_postcard(&legacy).unwrap();
+        assert_eq!(
+            history,
+            [
+                ChatMessage::new(Role::System, "You are a helpful assistant"),
+                ChatMessage::new(Role::User, "Hello"),
+                ChatMessage::new(Role::Assistant, "Hi there!"),
+            ]
+        );
+
+        let mut history = history;
+        history[2].metadata = Some(MessageMetadata {
+            model: Some("gpt-4".to_string()),
+            ..MessageMetadata::now()
+        });
+        #[cfg(feature = "functions")]
+        {
+            history[1].function_call = Some(crate::functions::FunctionCall {
+                name: "greet".to_string(),
+                arguments: "{}".to_string(),
+            });
+        }
+        assert_eq!(
+            from_postcard(&to_postcard(&history).unwrap()).unwrap(),
+            history
+        );
+    }
+}